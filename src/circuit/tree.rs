@@ -23,16 +23,16 @@ pub struct CTreePub<C:CS> {
 }
 
 #[derive(Clone, Signal)]
-#[Value = "TreeSec<C::Fr>"]
-pub struct CTreeSec<C:CS> {
-    pub proof_filled:CMerkleProof<C, {HEIGHT - OUTPLUSONELOG}>,
-    pub proof_free:CMerkleProof<C, {HEIGHT - OUTPLUSONELOG}>,
+#[Value = "TreeSec<C::Fr, L>"]
+pub struct CTreeSec<C:CS, const L: usize = { HEIGHT - OUTPLUSONELOG }> {
+    pub proof_filled:CMerkleProof<C, L>,
+    pub proof_free:CMerkleProof<C, L>,
     pub prev_leaf:CNum<C>
 }
 
-pub fn tree_update<C:CS, P:PoolParams<Fr=C::Fr>>(
+pub fn tree_update<C:CS, P:PoolParams<Fr=C::Fr>, const L: usize>(
     p: &CTreePub<C>,
-    s: &CTreeSec<C>,
+    s: &CTreeSec<C, L>,
     params: &P,
 ) {
     let index_filled = c_from_bits_le(s.proof_filled.path.as_slice());
@@ -44,7 +44,7 @@ pub fn tree_update<C:CS, P:PoolParams<Fr=C::Fr>>(
     }
 
     let mut zero_root_value = Num::ZERO;
-    for _ in 0..HEIGHT {
+    for _ in 0..L + OUTPLUSONELOG {
         zero_root_value = poseidon(&[zero_root_value, zero_root_value], params.compress());
     }
 
@@ -11,7 +11,7 @@ use fawkes_crypto::ff_uint::{Num, NumRepr};
 use crate::{circuit::{account::CAccount, note::CNote, key::{c_derive_key_eta, c_derive_key_p_d}}};
 use crate::native::tx::{TransferPub, TransferSec, Tx};
 use crate::native::params::PoolParams;
-use crate::constants::{HEIGHT, IN, OUT, BALANCE_SIZE_BITS, ENERGY_SIZE_BITS, POOLID_SIZE_BITS};
+use crate::constants::{self, HEIGHT, BALANCE_SIZE_BITS, ENERGY_SIZE_BITS, POOLID_SIZE_BITS};
 
 
 #[derive(Clone, Signal)]
@@ -25,17 +25,17 @@ pub struct CTransferPub<C:CS> {
 }
 
 #[derive(Clone, Signal)]
-#[Value = "Tx<C::Fr>"]
-pub struct CTx<C:CS> {
+#[Value = "Tx<C::Fr, IN, OUT>"]
+pub struct CTx<C:CS, const IN: usize = { constants::IN }, const OUT: usize = { constants::OUT }> {
     pub input: (CAccount<C>, SizedVec<CNote<C>, { IN }>),
     pub output: (CAccount<C>, SizedVec<CNote<C>, { OUT}>)
 }
 
 #[derive(Clone, Signal)]
-#[Value = "TransferSec<C::Fr>"]
-pub struct CTransferSec<C:CS> {
-    pub tx: CTx<C>,
-    pub in_proof: (CMerkleProof<C, { HEIGHT }>, SizedVec<CMerkleProof<C, { HEIGHT }>, { IN }>),
+#[Value = "TransferSec<C::Fr, IN, OUT, H>"]
+pub struct CTransferSec<C:CS, const IN: usize = { constants::IN }, const OUT: usize = { constants::OUT }, const H: usize = { HEIGHT }> {
+    pub tx: CTx<C, IN, OUT>,
+    pub in_proof: (CMerkleProof<C, H>, SizedVec<CMerkleProof<C, H>, { IN }>),
     pub eddsa_s: CNum<C>,
     pub eddsa_r: CNum<C>,
     pub eddsa_a: CNum<C>,
@@ -78,7 +78,8 @@ pub fn c_tx_verify<C:CS, P: PoolParams<Fr = C::Fr>>(
 }
 
 
-pub fn c_out_commitment_hash<C:CS, P:PoolParams<Fr=C::Fr>>(items:&[CNum<C>], params: &P) -> CNum<C> {
+// items are the output account and OUT notes, OUT+1 should be a power of two
+pub fn c_out_commitment_hash<C:CS, P:PoolParams<Fr=C::Fr>, const OUT: usize>(items:&[CNum<C>], params: &P) -> CNum<C> {
     assert!(items.len()==OUT+1);
     c_poseidon_merkle_tree_root(items, params.compress())
 }
@@ -109,11 +110,15 @@ pub fn c_parse_delta<C:CS, P:PoolParams<Fr=C::Fr>>(delta: &CNum<C>) -> (CNum<C>,
 
 
 
-pub fn c_transfer<C:CS, P:PoolParams<Fr=C::Fr>>(
+// IN, OUT and the tree height H are taken from the type of s, one build can have circuits of several shapes.
+// Indexes are compared with HEIGHT bits, so H must not exceed HEIGHT.
+pub fn c_transfer<C:CS, P:PoolParams<Fr=C::Fr>, const IN: usize, const OUT: usize, const H: usize>(
     p: &CTransferPub<C>,
-    s: &CTransferSec<C>,
+    s: &CTransferSec<C, IN, OUT, H>,
     params: &P,
 ) {
+    assert!(H <= HEIGHT, "tree height is out of range");
+
     //parse delta
     let (delta_value, delta_energy, current_index, poolid) = c_parse_delta::<C,P>(&p.delta);
     let mut total_value = delta_value;
@@ -157,7 +162,7 @@ pub fn c_transfer<C:CS, P:PoolParams<Fr=C::Fr>>(
     t.assert_zero();
 
     //check output     
-    let out_ch = c_out_commitment_hash(&out_hash, params);
+    let out_ch = c_out_commitment_hash::<_, _, OUT>(&out_hash, params);
     (&out_ch - &p.out_commit).assert_zero();
 
 
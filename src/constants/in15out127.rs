@@ -1,4 +0,0 @@
-pub const INPLUSONELOGCEIL:usize = 4;
-pub const IN:usize = (1<<INPLUSONELOGCEIL)-1; // 15
-pub const OUTPLUSONELOG: usize = 7;
-pub const OUT: usize = (1<<OUTPLUSONELOG)-1; // 127
\ No newline at end of file
@@ -1,4 +0,0 @@
-pub const INPLUSONELOGCEIL:usize = 1;
-pub const IN:usize = (1<<INPLUSONELOGCEIL)-1; // 1
-pub const OUTPLUSONELOG: usize = 7;
-pub const OUT: usize = (1<<OUTPLUSONELOG)-1; // 127
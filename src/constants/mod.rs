@@ -1,12 +1,11 @@
 use fawkes_crypto::ff_uint::{PrimeFieldParams, Uint};
 
-#[cfg_attr(feature="in1out127", path = "in1out127.rs")]
-#[cfg_attr(feature="in3out127", path = "in3out127.rs")]
-#[cfg_attr(feature="in7out127", path = "in7out127.rs")]
-#[cfg_attr(feature="in15out127", path = "in15out127.rs")]
-mod constants_inner;
-
-pub use constants_inner::*;
+// Default transfer shape, other shapes are set by IN and OUT const generics of the transfer types.
+// All transfer circuits share OUT, memos and the tree layout of OUT+1 leaves per transaction are built for it.
+pub const INPLUSONELOGCEIL:usize = 2;
+pub const IN:usize = (1<<INPLUSONELOGCEIL)-1; // 3
+pub const OUTPLUSONELOG: usize = 7;
+pub const OUT: usize = (1<<OUTPLUSONELOG)-1; // 127
 
 pub const HEIGHT: usize = 48;
 pub const DIVERSIFIER_SIZE_BITS: usize = 80;
@@ -32,4 +31,30 @@ pub fn account_size_bits<Fp:PrimeFieldParams>() -> usize {
 
 
 //fist 12 bytes from keccak256("ZeroPool")
-pub const ENCRYPTION_NONCE: [u8;12] = [0x5b, 0xbd, 0xff, 0xc6, 0xfe, 0x73, 0xc4, 0x60, 0xf1, 0xb2, 0xb8, 0x5d];
\ No newline at end of file
+pub const ENCRYPTION_NONCE: [u8;12] = [0x5b, 0xbd, 0xff, 0xc6, 0xfe, 0x73, 0xc4, 0x60, 0xf1, 0xb2, 0xb8, 0x5d];
+
+// versioned memo starts with zero byte, legacy memo starts with nonzero u32 items count
+pub const MEMO_VERSION_PREFIX: u8 = 0;
+// account and outgoing notes are encrypted with eta
+pub const MEMO_VERSION_LEGACY: u8 = 0;
+// account and outgoing notes are encrypted with ovk
+pub const MEMO_VERSION_OVK: u8 = 1;
+
+pub const MEMO_FLAG_FEE: u16 = 1;
+pub const MEMO_FLAG_EXTRA_DATA: u16 = 2;
+pub const MEMO_FLAG_DETECTION_TAGS: u16 = 4;
+pub const MEMO_FLAG_HARDENED_CIPHER: u16 = 8;
+
+pub const DETECTION_TAG_SIZE: usize = 4;
+pub const DETECTION_TAG_DOMAIN: &[u8] = b"ZeroPool/tag";
+
+// keccak256 of it reduced to the field is the second input of ovk hash
+pub const OVK_DOMAIN: &[u8] = b"ZeroPool/ovk";
+
+// hardened cipher prefixes every ciphertext with XChaCha20 nonce and key commitment
+pub const XCHACHA20_NONCE_SIZE: usize = 24;
+pub const KEY_COMMITMENT_SIZE: usize = 32;
+pub const HARDENED_CIPHER_OVERHEAD: usize = XCHACHA20_NONCE_SIZE + KEY_COMMITMENT_SIZE;
+pub const HARDENED_NONCE_DOMAIN: &[u8] = b"ZeroPool/nonce";
+pub const HARDENED_KEY_DOMAIN: &[u8] = b"ZeroPool/key";
+pub const HARDENED_COMMITMENT_DOMAIN: &[u8] = b"ZeroPool/commitment";
@@ -0,0 +1,118 @@
+use crate::{constants::{self, DIVERSIFIER_SIZE_BITS, POOLID_SIZE_BITS}, fawkes_crypto::{
+        native::ecc::EdwardsPoint,
+        ff_uint::{Num, PrimeField},
+        borsh::{BorshSerialize, BorshDeserialize},
+        rand::Rng,
+    }, native::{
+        params::PoolParams,
+        boundednum::BoundedNum,
+        key::derive_key_p_d,
+        cipher::keccak256,
+    }};
+
+use std::fmt::{self, Debug, Display};
+
+
+const POOLID_SIZE: usize = POOLID_SIZE_BITS / 8;
+const DIVERSIFIER_SIZE: usize = DIVERSIFIER_SIZE_BITS / 8;
+const CHECKSUM_SIZE: usize = 4;
+const ADDRESS_SIZE: usize = POOLID_SIZE + DIVERSIFIER_SIZE + constants::U256_SIZE + CHECKSUM_SIZE;
+
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AddressError {
+    InvalidBase58,
+    InvalidLength,
+    InvalidChecksum,
+    PoolIdMismatch,
+    PoolIdRange,
+    InvalidDiversifier,
+    InvalidPublicKey,
+}
+
+impl Display for AddressError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let msg = match self {
+            AddressError::InvalidBase58 => "address is not a valid base58 string",
+            AddressError::InvalidLength => "address has wrong length",
+            AddressError::InvalidChecksum => "address checksum does not match",
+            AddressError::PoolIdMismatch => "address belongs to another pool",
+            AddressError::PoolIdRange => "pool id is out of range",
+            AddressError::InvalidDiversifier => "address diversifier is out of range",
+            AddressError::InvalidPublicKey => "address public key is not on the JubJub subgroup",
+        };
+        write!(f, "{}", msg)
+    }
+}
+
+impl std::error::Error for AddressError {}
+
+
+// Shielded address of the note recipient. String form is base58 of
+// pool_id (3 bytes, big endian) || d (10 bytes) || p_d (32 bytes) || keccak256 checksum (4 bytes)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Address<Fr:PrimeField> {
+    pub d: BoundedNum<Fr, { DIVERSIFIER_SIZE_BITS }>,
+    pub p_d: Num<Fr>,
+}
+
+impl<Fr:PrimeField> Address<Fr> {
+    pub fn new<P:PoolParams<Fr=Fr>>(d:BoundedNum<Fr, { DIVERSIFIER_SIZE_BITS }>, eta:Num<Fr>, params:&P) -> Self {
+        Self {
+            d,
+            p_d: derive_key_p_d(d.to_num(), eta, params).x
+        }
+    }
+
+    // fresh diversified address, all of them are decrypted with the same eta
+    pub fn generate<R:Rng, P:PoolParams<Fr=Fr>>(eta:Num<Fr>, rng:&mut R, params:&P) -> Self {
+        Self::new(rng.gen(), eta, params)
+    }
+
+    pub fn is_own<P:PoolParams<Fr=Fr>>(&self, eta:Num<Fr>, params:&P) -> bool {
+        derive_key_p_d(self.d.to_num(), eta, params).x == self.p_d
+    }
+
+    pub fn encode(&self, pool_id:u32) -> Result<String, AddressError> {
+        if pool_id >> POOLID_SIZE_BITS != 0 {
+            return Err(AddressError::PoolIdRange);
+        }
+        let mut data = pool_id.to_be_bytes()[4 - POOLID_SIZE..].to_vec();
+        data.extend(self.d.try_to_vec().unwrap());
+        data.extend(self.p_d.try_to_vec().unwrap());
+        let checksum = keccak256(&data);
+        data.extend(&checksum[..CHECKSUM_SIZE]);
+        Ok(bs58::encode(data).into_string())
+    }
+
+    pub fn decode<P:PoolParams<Fr=Fr>>(s:&str, pool_id:u32, params:&P) -> Result<Self, AddressError> {
+        if pool_id >> POOLID_SIZE_BITS != 0 {
+            return Err(AddressError::PoolIdRange);
+        }
+        let data = bs58::decode(s).into_vec().map_err(|_| AddressError::InvalidBase58)?;
+        if data.len() != ADDRESS_SIZE {
+            return Err(AddressError::InvalidLength);
+        }
+
+        let (body, checksum) = data.split_at(ADDRESS_SIZE - CHECKSUM_SIZE);
+        if keccak256(body)[..CHECKSUM_SIZE] != *checksum {
+            return Err(AddressError::InvalidChecksum);
+        }
+
+        let (prefix, body) = body.split_at(POOLID_SIZE);
+        let mut pool_id_bytes = [0u8; 4];
+        pool_id_bytes[4 - POOLID_SIZE..].copy_from_slice(prefix);
+        if u32::from_be_bytes(pool_id_bytes) != pool_id {
+            return Err(AddressError::PoolIdMismatch);
+        }
+
+        let (d, p_d) = body.split_at(DIVERSIFIER_SIZE);
+        let d = BoundedNum::try_from_slice(d).map_err(|_| AddressError::InvalidDiversifier)?;
+        let p_d = Num::try_from_slice(p_d).map_err(|_| AddressError::InvalidPublicKey)?;
+        if EdwardsPoint::subgroup_decompress(p_d, params.jubjub()).is_none() {
+            return Err(AddressError::InvalidPublicKey);
+        }
+
+        Ok(Self {d, p_d})
+    }
+}
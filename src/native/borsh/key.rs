@@ -0,0 +1,52 @@
+use crate::{POOL_PARAMS, native::key::ViewingKey};
+use crate::fawkes_crypto::{
+    ff_uint::Num,
+    engines::bn256::Fr,
+    borsh::{BorshSerialize, BorshDeserialize},
+    serde::{Serialize, Deserialize, Serializer, Deserializer, de},
+};
+use std::io::{self, Write};
+
+
+// a should be on the JubJub subgroup and eta should be derived from it
+fn check_viewing_key(a:Num<Fr>, eta:Num<Fr>) -> Option<ViewingKey<Fr>> {
+    ViewingKey::new(a, &*POOL_PARAMS).filter(|vk| vk.eta == eta)
+}
+
+impl BorshSerialize for ViewingKey<Fr> {
+    fn serialize<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        BorshSerialize::serialize(&self.a, writer)?;
+        BorshSerialize::serialize(&self.eta, writer)
+    }
+}
+
+impl BorshDeserialize for ViewingKey<Fr> {
+    fn deserialize(buf: &mut &[u8]) -> io::Result<Self> {
+        let a = BorshDeserialize::deserialize(buf)?;
+        let eta = BorshDeserialize::deserialize(buf)?;
+        check_viewing_key(a, eta).ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "invalid viewing key"))
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct ViewingKeyData {
+    a: Num<Fr>,
+    eta: Num<Fr>,
+}
+
+impl Serialize for ViewingKey<Fr> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let d = ViewingKeyData {
+            a: self.a,
+            eta: self.eta
+        };
+        Serialize::serialize(&d, serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for ViewingKey<Fr> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let d: ViewingKeyData = Deserialize::deserialize(deserializer)?;
+        check_viewing_key(d.a, d.eta).ok_or_else(|| de::Error::custom("invalid viewing key"))
+    }
+}
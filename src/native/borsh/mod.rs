@@ -1,3 +1,5 @@
 pub mod account;
 pub mod note;
-pub mod boundednum;
\ No newline at end of file
+pub mod boundednum;
+pub mod tx;
+pub mod key;
\ No newline at end of file
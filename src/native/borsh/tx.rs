@@ -0,0 +1,54 @@
+use crate::native::tx::*;
+use crate::fawkes_crypto::{
+    borsh::{BorshSerialize, BorshDeserialize},
+    serde::{Serialize, Deserialize, Serializer, Deserializer, de},
+};
+use std::io::{self, Write};
+
+
+impl BorshSerialize for Delta {
+    fn serialize<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        BorshSerialize::serialize(&self.value, writer)?;
+        BorshSerialize::serialize(&self.energy, writer)?;
+        BorshSerialize::serialize(&self.index, writer)?;
+        BorshSerialize::serialize(&self.pool_id, writer)
+    }
+}
+
+impl BorshDeserialize for Delta {
+    fn deserialize(buf: &mut &[u8]) -> io::Result<Self> {
+        Delta::new(
+            BorshDeserialize::deserialize(buf)?,
+            BorshDeserialize::deserialize(buf)?,
+            BorshDeserialize::deserialize(buf)?,
+            BorshDeserialize::deserialize(buf)?
+        ).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct DeltaData {
+    value: i64,
+    energy: i128,
+    index: u64,
+    pool_id: u32,
+}
+
+impl Serialize for Delta {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let d = DeltaData {
+            value: self.value,
+            energy: self.energy,
+            index: self.index,
+            pool_id: self.pool_id
+        };
+        Serialize::serialize(&d, serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Delta {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let d: DeltaData = Deserialize::deserialize(deserializer)?;
+        Delta::new(d.value, d.energy, d.index, d.pool_id).map_err(de::Error::custom)
+    }
+}
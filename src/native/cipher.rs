@@ -1,13 +1,14 @@
 use crate::{
     fawkes_crypto::{
-        ff_uint::{Num, seedbox::{SeedboxChaCha20, SeedBox, SeedBoxGen}},
+        ff_uint::{Num, PrimeField, seedbox::{SeedboxChaCha20, SeedBox, SeedBoxGen}},
         borsh::{BorshSerialize, BorshDeserialize},
-        native::ecc::{EdwardsPoint},
+        native::ecc::{EdwardsPoint, JubJubParams},
 
     },
     native::{
         account::Account,
         note::Note,
+        tx::out_commitment_hash,
         params::PoolParams,
         key::{derive_key_a, derive_key_p_d}
     },
@@ -15,11 +16,13 @@ use crate::{
 };
 
 use sha3::{Digest, Keccak256};
+use std::fmt::{self, Display};
+use std::marker::PhantomData;
 
-use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use chacha20poly1305::{ChaCha20Poly1305, XChaCha20Poly1305, Key, Nonce, XNonce};
 use chacha20poly1305::aead::{Aead, NewAead};
 
-fn keccak256(data:&[u8])->[u8;constants::U256_SIZE] {
+pub(crate) fn keccak256(data:&[u8])->[u8;constants::U256_SIZE] {
     let mut hasher = Keccak256::new();
     hasher.update(data);
     let mut res = [0u8;constants::U256_SIZE];
@@ -44,8 +47,220 @@ fn symcipher_decode(key:&[u8], data:&[u8])->Option<Vec<u8>> {
 
 }
 
+// encryption key and commitment to the original key, so the ciphertext can be opened only with one key
+fn hardened_keys(key:&[u8]) -> ([u8;constants::U256_SIZE], [u8;constants::KEY_COMMITMENT_SIZE]) {
+    (keccak256(&[key, constants::HARDENED_KEY_DOMAIN].concat()), keccak256(&[key, constants::HARDENED_COMMITMENT_DOMAIN].concat()))
+}
+
+// nonce is derived from the key and the data, so repeated key leaks only equality of the messages
+fn symcipher_hardened_encode(key:&[u8], data:&[u8])->Vec<u8> {
+    assert!(key.len()==constants::U256_SIZE);
+    let nonce = keccak256(&[constants::HARDENED_NONCE_DOMAIN, key, data].concat());
+    let mut nonce_bytes = [0u8;constants::XCHACHA20_NONCE_SIZE];
+    nonce_bytes.copy_from_slice(&nonce[..constants::XCHACHA20_NONCE_SIZE]);
+    let nonce = XNonce::from(nonce_bytes);
+    let (enc_key, commitment) = hardened_keys(key);
+    let cipher = XChaCha20Poly1305::new(&Key::from(enc_key));
+    [&nonce[..], &commitment[..], &cipher.encrypt(&nonce, data).unwrap()].concat()
+}
+
+fn symcipher_hardened_decode(key:&[u8], data:&[u8])->Option<Vec<u8>> {
+    assert!(key.len()==constants::U256_SIZE);
+    if data.len() < constants::HARDENED_CIPHER_OVERHEAD {
+        return None;
+    }
+    let (nonce, data) = data.split_at(constants::XCHACHA20_NONCE_SIZE);
+    let (commitment, data) = data.split_at(constants::KEY_COMMITMENT_SIZE);
+    let (enc_key, expected_commitment) = hardened_keys(key);
+    if commitment != expected_commitment {
+        return None;
+    }
+    let mut nonce_bytes = [0u8;constants::XCHACHA20_NONCE_SIZE];
+    nonce_bytes.copy_from_slice(nonce);
+    let cipher = XChaCha20Poly1305::new(&Key::from(enc_key));
+    cipher.decrypt(&XNonce::from(nonce_bytes), data).ok()
+}
+
+fn symcipher_seal(hardened:bool, key:&[u8], data:&[u8])->Vec<u8> {
+    if hardened {
+        symcipher_hardened_encode(key, data)
+    } else {
+        symcipher_encode(key, data)
+    }
+}
+
+fn symcipher_open(hardened:bool, key:&[u8], data:&[u8])->Option<Vec<u8>> {
+    if hardened {
+        symcipher_hardened_decode(key, data)
+    } else {
+        symcipher_decode(key, data)
+    }
+}
+
+
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MemoError {
+    Truncated,
+    UnknownVersion,
+    UnknownFlags,
+    VersionMismatch,
+    InvalidItemsNum,
+    InvalidFieldElement,
+    InvalidPoint,
+    DecryptionFailed,
+    InvalidPlaintext,
+    HashMismatch,
+}
+
+impl Display for MemoError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let msg = match self {
+            MemoError::Truncated => "memo is truncated",
+            MemoError::UnknownVersion => "memo version is unknown",
+            MemoError::UnknownFlags => "memo has unknown flags",
+            MemoError::VersionMismatch => "memo version does not match the key",
+            MemoError::InvalidItemsNum => "memo items number is out of range",
+            MemoError::InvalidFieldElement => "memo number is out of the field",
+            MemoError::InvalidPoint => "memo ephemeral key is not on the JubJub subgroup",
+            MemoError::DecryptionFailed => "memo ciphertext can't be decrypted with the key",
+            MemoError::InvalidPlaintext => "decrypted memo data is malformed",
+            MemoError::HashMismatch => "decrypted data does not match the committed hash",
+        };
+        write!(f, "{}", msg)
+    }
+}
+
+impl std::error::Error for MemoError {}
+
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct MemoHeader {
+    pub version: u8,
+    pub fee: Option<u64>,
+    pub extra_data: Option<Vec<u8>>,
+    // one tag per note, computed by encrypt_with_header when set to Some.
+    // Tag is checked with one scalar multiplication, trial decryption needs two and the AEAD
+    pub detection_tags: Option<Vec<DetectionTag>>,
+    // XChaCha20Poly1305 with derived nonce and key commitment instead of ChaCha20Poly1305 with the fixed nonce
+    pub hardened: bool,
+}
+
+pub type DetectionTag = [u8; constants::DETECTION_TAG_SIZE];
+
+impl MemoHeader {
+    pub fn new(version: u8) -> Self {
+        Self {
+            version,
+            ..Default::default()
+        }
+    }
+
+    pub fn with_detection_tags(mut self) -> Self {
+        self.detection_tags = Some(vec![]);
+        self
+    }
+
+    pub fn with_hardened_cipher(mut self) -> Self {
+        self.hardened = true;
+        self
+    }
+
+    // size added to each ciphertext of the memo
+    fn cipher_overhead(&self) -> usize {
+        if self.hardened {
+            constants::POLY_1305_TAG_SIZE + constants::HARDENED_CIPHER_OVERHEAD
+        } else {
+            constants::POLY_1305_TAG_SIZE
+        }
+    }
+
+    fn flags(&self) -> u16 {
+        let mut flags = 0;
+        if self.fee.is_some() {
+            flags |= constants::MEMO_FLAG_FEE;
+        }
+        if self.extra_data.is_some() {
+            flags |= constants::MEMO_FLAG_EXTRA_DATA;
+        }
+        if self.detection_tags.is_some() {
+            flags |= constants::MEMO_FLAG_DETECTION_TAGS;
+        }
+        if self.hardened {
+            flags |= constants::MEMO_FLAG_HARDENED_CIPHER;
+        }
+        flags
+    }
+
+    fn write(&self, res: &mut Vec<u8>) {
+        res.push(constants::MEMO_VERSION_PREFIX);
+        res.push(self.version);
+        self.flags().serialize(res).unwrap();
+        if let Some(fee) = self.fee {
+            fee.serialize(res).unwrap();
+        }
+        if let Some(extra_data) = self.extra_data.as_ref() {
+            (extra_data.len() as u32).serialize(res).unwrap();
+            res.extend(extra_data);
+        }
+        if let Some(detection_tags) = self.detection_tags.as_ref() {
+            (detection_tags.len() as u32).serialize(res).unwrap();
+            detection_tags.iter().for_each(|t| res.extend(t));
+        }
+    }
+}
+
+// returns header and body of the memo, memo without header is legacy memo of version 0
+pub fn parse_memo_header(memo:&[u8]) -> Option<(MemoHeader, &[u8])> {
+    try_parse_memo_header(memo).ok()
+}
+
+pub fn try_parse_memo_header(mut memo:&[u8]) -> Result<(MemoHeader, &[u8]), MemoError> {
+    if *memo.first().ok_or(MemoError::Truncated)? != constants::MEMO_VERSION_PREFIX {
+        return Ok((MemoHeader::new(constants::MEMO_VERSION_LEGACY), memo));
+    }
+    buf_take(&mut memo, 1)?;
+
+    let version = buf_read::<u8>(&mut memo)?;
+    if version > constants::MEMO_VERSION_OVK {
+        return Err(MemoError::UnknownVersion);
+    }
+
+    let flags = buf_read::<u16>(&mut memo)?;
+    if flags & !(constants::MEMO_FLAG_FEE | constants::MEMO_FLAG_EXTRA_DATA | constants::MEMO_FLAG_DETECTION_TAGS | constants::MEMO_FLAG_HARDENED_CIPHER) != 0 {
+        return Err(MemoError::UnknownFlags);
+    }
+
+    let fee = if flags & constants::MEMO_FLAG_FEE != 0 {
+        Some(buf_read::<u64>(&mut memo)?)
+    } else {
+        None
+    };
+
+    let extra_data = if flags & constants::MEMO_FLAG_EXTRA_DATA != 0 {
+        let len = buf_read::<u32>(&mut memo)? as usize;
+        Some(buf_take(&mut memo, len)?.to_vec())
+    } else {
+        None
+    };
+
+    let detection_tags = if flags & constants::MEMO_FLAG_DETECTION_TAGS != 0 {
+        let len = buf_read::<u32>(&mut memo)? as usize;
+        if len > constants::OUT {
+            return Err(MemoError::InvalidItemsNum);
+        }
+        Some((0..len).map(|_| buf_read::<DetectionTag>(&mut memo)).collect::<Result<Vec<_>, _>>()?)
+    } else {
+        None
+    };
 
+    let hardened = flags & constants::MEMO_FLAG_HARDENED_CIPHER != 0;
 
+    Ok((MemoHeader {version, fee, extra_data, detection_tags, hardened}, memo))
+}
+
+
+// legacy memo without header, account and outgoing notes are decrypted with eta
 pub fn encrypt<P: PoolParams>(
     entropy: &[u8],
     eta:Num<P::Fr>,
@@ -53,6 +268,63 @@ pub fn encrypt<P: PoolParams>(
     note: &[Note<P::Fr>],
     params:&P
 ) -> Vec<u8> {
+    let mut res = vec![];
+    _encrypt(&mut res, false, entropy, eta, account, note, params);
+    res
+}
+
+// account and outgoing notes are decrypted only with ovk, incoming notes are still decrypted with eta of the receiver
+pub fn encrypt_with_ovk<P: PoolParams>(
+    entropy: &[u8],
+    ovk:Num<P::Fr>,
+    account: Account<P::Fr>,
+    note: &[Note<P::Fr>],
+    params:&P
+) -> Vec<u8> {
+    encrypt_with_header(entropy, &MemoHeader::new(constants::MEMO_VERSION_OVK), ovk, account, note, params)
+}
+
+// out_key is eta for MEMO_VERSION_LEGACY and ovk for MEMO_VERSION_OVK
+pub fn encrypt_with_header<P: PoolParams>(
+    entropy: &[u8],
+    header: &MemoHeader,
+    out_key:Num<P::Fr>,
+    account: Account<P::Fr>,
+    note: &[Note<P::Fr>],
+    params:&P
+) -> Vec<u8> {
+    assert!(header.version <= constants::MEMO_VERSION_OVK, "unknown memo version");
+    let mut body = vec![];
+    let note_ecdh = _encrypt(&mut body, header.hardened, entropy, out_key, account, note, params);
+
+    let mut header = header.clone();
+    if header.detection_tags.is_some() {
+        header.detection_tags = Some(note_ecdh.into_iter().map(detection_tag).collect());
+    }
+
+    let mut res = vec![];
+    header.write(&mut res);
+    res.extend(body);
+    res
+}
+
+// tag depends on the square of ECDH x coordinate, so the receiver can skip the subgroup check of a_pub
+fn detection_tag<Fr:PrimeField>(ecdh_x:Num<Fr>) -> DetectionTag {
+    let data = [&ecdh_x.square().try_to_vec().unwrap()[..], constants::DETECTION_TAG_DOMAIN].concat();
+    let mut res = [0u8; constants::DETECTION_TAG_SIZE];
+    res.copy_from_slice(&keccak256(&data)[..constants::DETECTION_TAG_SIZE]);
+    res
+}
+
+fn _encrypt<P: PoolParams>(
+    res: &mut Vec<u8>,
+    hardened: bool,
+    entropy: &[u8],
+    out_key:Num<P::Fr>,
+    account: Account<P::Fr>,
+    note: &[Note<P::Fr>],
+    params:&P
+) -> Vec<Num<P::Fr>> {
     let nozero_notes_num = note.len();
     let nozero_items_num = nozero_notes_num+1;
 
@@ -62,7 +334,7 @@ pub fn encrypt<P: PoolParams>(
     let account_data = {
         let mut account_key = [0u8;constants::U256_SIZE];
         sb.fill_bytes(&mut account_key);
-        let account_ciphertext = symcipher_encode(&account_key, &account.try_to_vec().unwrap());
+        let account_ciphertext = symcipher_seal(hardened, &account_key, &account.try_to_vec().unwrap());
         (account_key, account_ciphertext)
     };
     
@@ -72,149 +344,311 @@ pub fn encrypt<P: PoolParams>(
         let p_d = EdwardsPoint::subgroup_decompress(e.p_d, params.jubjub()).unwrap();
         let ecdh =  p_d.mul(a, params.jubjub());
         let key = keccak256(&ecdh.x.try_to_vec().unwrap());
-        let ciphertext = symcipher_encode(&key, &e.try_to_vec().unwrap());
+        let ciphertext = symcipher_seal(hardened, &key, &e.try_to_vec().unwrap());
         let a_pub = derive_key_p_d(e.d.to_num(), a, params); 
-        (a_pub.x, key, ciphertext)
+        (a_pub.x, key, ciphertext, ecdh.x)
         
     }).collect::<Vec<_>>();
 
     let shared_secret_data = {
         let a_p_pub = derive_key_a(sb.gen(), params);
-        let ecdh = a_p_pub.mul(eta.to_other_reduced(), params.jubjub());
+        let ecdh = a_p_pub.mul(out_key.to_other_reduced(), params.jubjub());
         let key = keccak256(&ecdh.x.try_to_vec().unwrap());
         let text:Vec<u8> = core::iter::once(&account_data.0[..]).chain(notes_data.iter().map(|e| &e.1[..])).collect::<Vec<_>>().concat();
-        let ciphertext = symcipher_encode(&key, &text);
+        let ciphertext = symcipher_seal(hardened, &key, &text);
         (a_p_pub.x, ciphertext)
     };
 
-    let mut res = vec![];
-
-    (nozero_items_num as u32).serialize(&mut res).unwrap();
-    account.hash(params).serialize(&mut res).unwrap();
+    (nozero_items_num as u32).serialize(res).unwrap();
+    account.hash(params).serialize(res).unwrap();
 
     for e in note.iter() {
-        e.hash(params).serialize(&mut res).unwrap();
+        e.hash(params).serialize(res).unwrap();
     }
-    shared_secret_data.0.serialize(&mut res).unwrap();
+    shared_secret_data.0.serialize(res).unwrap();
     res.extend(&shared_secret_data.1);
 
     res.extend(&account_data.1);
 
     notes_data.iter().for_each(|nd|{
-        nd.0.serialize(&mut res).unwrap();
+        nd.0.serialize(res).unwrap();
         res.extend(&nd.2);
     });
 
-    res
+    notes_data.into_iter().map(|nd| nd.3).collect()
 }
 
 
-fn buf_take<'a>(memo: &mut &'a[u8], size:usize) -> Option<&'a[u8]> {
+pub(crate) type DecryptedOut<Fr> = (Account<Fr>, Vec<Note<Fr>>);
+pub(crate) type DecryptedSlot<Fr> = Result<Option<Note<Fr>>, MemoError>;
+
+fn buf_take<'a>(memo: &mut &'a[u8], size:usize) -> Result<&'a[u8], MemoError> {
     if memo.len() < size {
-        None
+        Err(MemoError::Truncated)
     } else {
         let res = &memo[0..size];
         *memo = &memo[size..];
-        Some(res)
+        Ok(res)
     }
 }
 
-pub fn decrypt_out<P: PoolParams>(eta:Num<P::Fr>, mut memo:&[u8], params:&P)->Option<(Account<P::Fr>, Vec<Note<P::Fr>>)> {
-    let num_size = constants::num_size_bits::<P::Fr>()/8;
-    let account_size = constants::account_size_bits::<P::Fr>()/8;
-    let note_size = constants::note_size_bits::<P::Fr>()/8;
+fn buf_read<T:BorshDeserialize>(memo: &mut &[u8]) -> Result<T, MemoError> {
+    T::deserialize(memo).map_err(|_| MemoError::Truncated)
+}
+
+// symmetric key from the ephemeral public key x coordinate and the secret key
+pub(crate) fn ecdh_key<P: PoolParams>(x:Num<P::Fr>, key:Num<P::Fr>, params:&P) -> Result<[u8;constants::U256_SIZE], MemoError> {
+    let p = EdwardsPoint::subgroup_decompress(x, params.jubjub()).ok_or(MemoError::InvalidPoint)?;
+    let ecdh = p.mul(key.to_other_reduced(), params.jubjub());
+    Ok(keccak256(&ecdh.x.try_to_vec().unwrap()))
+}
 
+// x coordinate of ECDH up to the sign. Point is decompressed with any root of y and without
+// the subgroup check, which saves one scalar multiplication, the result is used only for detection tags
+pub(crate) fn ecdh_x_unchecked<P: PoolParams>(x:Num<P::Fr>, key:Num<P::Fr>, params:&P) -> Result<Num<P::Fr>, MemoError> {
+    let x2 = x.square();
+    let y = ((x2 + Num::ONE) / (Num::ONE - params.jubjub().edwards_d() * x2)).sqrt().ok_or(MemoError::InvalidPoint)?;
+    Ok(EdwardsPoint {x, y}.mul(key.to_other_reduced(), params.jubjub()).x)
+}
 
-    let nozero_items_num = u32::deserialize(&mut memo).ok()? as usize;
-    if nozero_items_num == 0 {
-        return None;
+// Memo layout, all parts are borrowed from the memo buffer:
+// header, items count u32, account hash, note hashes, a_p, shared secret ciphertext, account ciphertext,
+// then (a_pub, ciphertext) for each note. Up to constants::OUT notes.
+#[derive(Clone, Debug)]
+pub struct MemoView<'a, Fr:PrimeField> {
+    header: MemoHeader,
+    items_num: usize,
+    hashes: &'a [u8],
+    a_p: &'a [u8],
+    shared_secret_ciphertext: &'a [u8],
+    account_ciphertext: &'a [u8],
+    notes: &'a [u8],
+    _fr: PhantomData<Fr>,
+}
+
+impl<'a, Fr:PrimeField> MemoView<'a, Fr> {
+    pub fn parse(memo:&'a [u8]) -> Result<Self, MemoError> {
+        let num_size = constants::num_size_bits::<Fr>()/8;
+        let account_size = constants::account_size_bits::<Fr>()/8;
+        let note_size = constants::note_size_bits::<Fr>()/8;
+
+        let (header, mut memo) = try_parse_memo_header(memo)?;
+
+        let items_num = buf_read::<u32>(&mut memo)? as usize;
+        if items_num == 0 || items_num > constants::OUT + 1 {
+            return Err(MemoError::InvalidItemsNum);
+        }
+
+        let hashes = buf_take(&mut memo, items_num * num_size)?;
+        let a_p = buf_take(&mut memo, num_size)?;
+        let overhead = header.cipher_overhead();
+        let shared_secret_ciphertext = buf_take(&mut memo, items_num * constants::U256_SIZE + overhead)?;
+        let account_ciphertext = buf_take(&mut memo, account_size + overhead)?;
+        let notes = buf_take(&mut memo, (items_num - 1) * (num_size + note_size + overhead))?;
+
+        let res = Self {
+            header,
+            items_num,
+            hashes,
+            a_p,
+            shared_secret_ciphertext,
+            account_ciphertext,
+            notes,
+            _fr: PhantomData,
+        };
+
+        // all field elements are checked once, so accessors can't fail
+        let nums = hashes.chunks(num_size)
+            .chain(core::iter::once(a_p))
+            .chain((0..res.notes_num()).map(|i| res.note_data(i).0));
+        for n in nums {
+            Num::<Fr>::try_from_slice(n).map_err(|_| MemoError::InvalidFieldElement)?;
+        }
+
+        Ok(res)
     }
 
-    let nozero_notes_num = nozero_items_num - 1;
-    let shared_secret_ciphertext_size = nozero_items_num * constants::U256_SIZE + constants::POLY_1305_TAG_SIZE;
+    fn note_data(&self, i:usize) -> (&'a [u8], &'a [u8]) {
+        let num_size = constants::num_size_bits::<Fr>()/8;
+        let note_size = constants::note_size_bits::<Fr>()/8;
+        let item_size = num_size + note_size + self.header.cipher_overhead();
+        self.notes[i * item_size..(i + 1) * item_size].split_at(num_size)
+    }
 
-    let account_hash = Num::deserialize(&mut memo).ok()?;
-    let note_hash = (0..nozero_notes_num).map(|_| Num::deserialize(&mut memo)).collect::<Result<Vec<_>, _>>().ok()?;
+    fn num(data:&[u8]) -> Num<Fr> {
+        Num::try_from_slice(data).unwrap()
+    }
+
+    pub fn header(&self) -> &MemoHeader {
+        &self.header
+    }
+
+    // account and notes
+    pub fn items_num(&self) -> usize {
+        self.items_num
+    }
+
+    pub fn notes_num(&self) -> usize {
+        self.items_num - 1
+    }
+
+    pub fn account_hash(&self) -> Num<Fr> {
+        self.item_hash(0)
+    }
+
+    pub fn note_hash(&self, i:usize) -> Num<Fr> {
+        self.item_hash(i + 1)
+    }
 
+    // account hash followed by note hashes, as they are committed in out_commit
+    pub fn item_hash(&self, i:usize) -> Num<Fr> {
+        let num_size = constants::num_size_bits::<Fr>()/8;
+        Self::num(&self.hashes[i * num_size..(i + 1) * num_size])
+    }
+
+    pub fn item_hashes(&self) -> Vec<Num<Fr>> {
+        (0..self.items_num).map(|i| self.item_hash(i)).collect()
+    }
+
+    pub fn a_p(&self) -> Num<Fr> {
+        Self::num(self.a_p)
+    }
+
+    pub fn shared_secret_ciphertext(&self) -> &'a [u8] {
+        self.shared_secret_ciphertext
+    }
+
+    pub fn account_ciphertext(&self) -> &'a [u8] {
+        self.account_ciphertext
+    }
+
+    pub fn note_a_pub(&self, i:usize) -> Num<Fr> {
+        Self::num(self.note_data(i).0)
+    }
+
+    pub fn note_ciphertext(&self, i:usize) -> &'a [u8] {
+        self.note_data(i).1
+    }
+}
+
+pub fn decrypt_out<P: PoolParams>(eta:Num<P::Fr>, memo:&[u8], params:&P)->Option<DecryptedOut<P::Fr>> {
+    try_decrypt_out(eta, memo, params).ok()
+}
+
+pub fn decrypt_out_with_ovk<P: PoolParams>(ovk:Num<P::Fr>, memo:&[u8], params:&P)->Option<DecryptedOut<P::Fr>> {
+    try_decrypt_out_with_ovk(ovk, memo, params).ok()
+}
+
+pub fn try_decrypt_out<P: PoolParams>(eta:Num<P::Fr>, memo:&[u8], params:&P)->Result<DecryptedOut<P::Fr>, MemoError> {
+    let view = MemoView::parse(memo)?;
+    if view.header().version != constants::MEMO_VERSION_LEGACY {
+        return Err(MemoError::VersionMismatch);
+    }
+    decrypt_out_view(&|x| ecdh_key(x, eta, params), &view, params)
+}
+
+pub fn try_decrypt_out_with_ovk<P: PoolParams>(ovk:Num<P::Fr>, memo:&[u8], params:&P)->Result<DecryptedOut<P::Fr>, MemoError> {
+    let view = MemoView::parse(memo)?;
+    if view.header().version != constants::MEMO_VERSION_OVK {
+        return Err(MemoError::VersionMismatch);
+    }
+    decrypt_out_view(&|x| ecdh_key(x, ovk, params), &view, params)
+}
+
+pub(crate) fn decrypt_out_view<P: PoolParams, F: Fn(Num<P::Fr>) -> Result<[u8;constants::U256_SIZE], MemoError>>(ecdh:&F, view:&MemoView<P::Fr>, params:&P)->Result<DecryptedOut<P::Fr>, MemoError> {
     let shared_secret_text = {
-        let a_p = EdwardsPoint::subgroup_decompress(Num::deserialize(&mut memo).ok()?, params.jubjub())?;
-        let ecdh = a_p.mul(eta.to_other_reduced(), params.jubjub());
-        let key = keccak256(&ecdh.x.try_to_vec().unwrap());
-        let ciphertext = buf_take(&mut memo, shared_secret_ciphertext_size)?;
-        symcipher_decode(&key, ciphertext)?
+        let key = ecdh(view.a_p())?;
+        symcipher_open(view.header().hardened, &key, view.shared_secret_ciphertext()).ok_or(MemoError::DecryptionFailed)?
     };
     let mut shared_secret_text_ptr =&shared_secret_text[..];
 
-    let account_key= <[u8;constants::U256_SIZE]>::deserialize(&mut shared_secret_text_ptr).ok()?;
-    let note_key = (0..nozero_notes_num).map(|_| <[u8;constants::U256_SIZE]>::deserialize(&mut shared_secret_text_ptr)).collect::<Result<Vec<_>,_>>().ok()?;
+    let account_key = buf_read::<[u8;constants::U256_SIZE]>(&mut shared_secret_text_ptr)?;
+    let note_key = (0..view.notes_num()).map(|_| buf_read::<[u8;constants::U256_SIZE]>(&mut shared_secret_text_ptr)).collect::<Result<Vec<_>,_>>()?;
 
-    let account_ciphertext = buf_take(&mut memo, account_size+constants::POLY_1305_TAG_SIZE)?;
-    let account_text = symcipher_decode(&account_key, account_ciphertext)?;
-    let account = Account::try_from_slice(&account_text).ok()?;
+    let account_text = symcipher_open(view.header().hardened, &account_key, view.account_ciphertext()).ok_or(MemoError::DecryptionFailed)?;
+    let account = Account::try_from_slice(&account_text).map_err(|_| MemoError::InvalidPlaintext)?;
 
-    if account.hash(params)!= account_hash {
-        return None;
+    if account.hash(params)!= view.account_hash() {
+        return Err(MemoError::HashMismatch);
     }
 
-    let note = (0..nozero_notes_num).map(|i| {
-        buf_take(&mut memo, num_size)?;
-        let ciphertext = buf_take(&mut memo, note_size+constants::POLY_1305_TAG_SIZE)?;
-        let text = symcipher_decode(&note_key[i], ciphertext)?;
-        let note = Note::try_from_slice(&text).ok()?;
-        if note.hash(params) != note_hash[i] {
-            None
+    let note = (0..view.notes_num()).map(|i| {
+        let text = symcipher_open(view.header().hardened, &note_key[i], view.note_ciphertext(i)).ok_or(MemoError::DecryptionFailed)?;
+        let note = Note::try_from_slice(&text).map_err(|_| MemoError::InvalidPlaintext)?;
+        if note.hash(params) != view.note_hash(i) {
+            Err(MemoError::HashMismatch)
         } else {
-            Some(note)
+            Ok(note)
         }
-    }).collect::<Option<Vec<_>>>()?;
+    }).collect::<Result<Vec<_>, _>>()?;
     
-    Some((account, note))
+    Ok((account, note))
 }
 
-fn _decrypt_in<P: PoolParams>(eta:Num<P::Fr>, mut memo:&[u8], params:&P)->Option<Vec<Option<Note<P::Fr>>>> {
-    let num_size = constants::num_size_bits::<P::Fr>()/8;
-    let account_size = constants::account_size_bits::<P::Fr>()/8;
-    let note_size = constants::note_size_bits::<P::Fr>()/8;
+// note slot which is not addressed to the key is Ok(None)
+pub(crate) fn decrypt_in_view<P: PoolParams, F: Fn(Num<P::Fr>) -> Result<[u8;constants::U256_SIZE], MemoError>>(ecdh:&F, view:&MemoView<P::Fr>, params:&P)->Vec<DecryptedSlot<P::Fr>> {
+    (0..view.notes_num()).map(|i| {
+        let key = ecdh(view.note_a_pub(i))?;
+        let text = match symcipher_open(view.header().hardened, &key, view.note_ciphertext(i)) {
+            Some(text) => text,
+            None => return Ok(None)
+        };
+        let note = Note::try_from_slice(&text).map_err(|_| MemoError::InvalidPlaintext)?;
+        if note.hash(params) != view.note_hash(i) {
+            Err(MemoError::HashMismatch)
+        } else {
+            Ok(Some(note))
+        }
+    }).collect()
+}
 
+fn _decrypt_in<P: PoolParams>(eta:Num<P::Fr>, memo:&[u8], params:&P)->Result<Vec<DecryptedSlot<P::Fr>>, MemoError> {
+    let view = MemoView::parse(memo)?;
+    Ok(decrypt_in_view(&|x| ecdh_key(x, eta, params), &view, params))
+}
 
-    let nozero_items_num = u32::deserialize(&mut memo).ok()? as usize;
-    if nozero_items_num == 0 {
-        return None;
+pub fn decrypt_in<P: PoolParams>(eta:Num<P::Fr>, memo:&[u8], params:&P)->Vec<Option<Note<P::Fr>>> {
+    match _decrypt_in(eta, memo, params) {
+        Ok(res) => res.into_iter().map(|n| n.ok().flatten()).collect(),
+        Err(_) => vec![]
     }
+}
 
-    let nozero_notes_num = nozero_items_num - 1;
-    let shared_secret_ciphertext_size = nozero_items_num * constants::U256_SIZE + constants::POLY_1305_TAG_SIZE;
-
-    buf_take(&mut memo, num_size)?;
-    let note_hash = (0..nozero_notes_num).map(|_| Num::deserialize(&mut memo)).collect::<Result<Vec<_>, _>>().ok()?;
-
-    buf_take(&mut memo, num_size)?;
-    buf_take(&mut memo, shared_secret_ciphertext_size)?;
-    buf_take(&mut memo, account_size+constants::POLY_1305_TAG_SIZE)?;
+// fails on any corrupted note slot, foreign notes are None
+pub fn try_decrypt_in<P: PoolParams>(eta:Num<P::Fr>, memo:&[u8], params:&P)->Result<Vec<Option<Note<P::Fr>>>, MemoError> {
+    _decrypt_in(eta, memo, params)?.into_iter().collect()
+}
 
 
-    let note = (0..nozero_notes_num).map(|i| {
-        let a_pub = EdwardsPoint::subgroup_decompress(Num::deserialize(&mut memo).ok()?, params.jubjub())?;
-        let ecdh = a_pub.mul(eta.to_other_reduced(), params.jubjub());
-        let key = keccak256(&ecdh.x.try_to_vec().unwrap());
+// account and note hashes of the memo padded with zero notes rebuild out_commit of the transaction
+// with constants::OUT notes
+pub fn verify_memo_commitment<P: PoolParams>(memo:&[u8], out_commit:Num<P::Fr>, params:&P) -> bool {
+    let view = match MemoView::parse(memo) {
+        Ok(view) => view,
+        Err(_) => return false
+    };
+    let zero_note_hash = Note::zero().hash(params);
 
-        let ciphertext = buf_take(&mut memo, note_size+constants::POLY_1305_TAG_SIZE)?;
-        let text = symcipher_decode(&key, ciphertext)?;
-        let note = Note::try_from_slice(&text).ok()?;
-        if note.hash(params) != note_hash[i] {
-            None
-        } else {
-            Some(note)
-        }
-    }).collect::<Vec<Option<_>>>();
+    let mut hashes = view.item_hashes();
+    hashes.resize(constants::OUT + 1, zero_note_hash);
+    out_commitment_hash::<_, { constants::OUT }>(&hashes, params) == out_commit
+}
 
-    Some(note)
+// ecdh_x is ecdh_x_unchecked with the key of the receiver
+pub(crate) fn quick_check_view<Fr: PrimeField, F: Fn(Num<Fr>) -> Result<Num<Fr>, MemoError>>(ecdh_x:&F, view:&MemoView<Fr>) -> bool {
+    match view.header().detection_tags.as_ref() {
+        Some(tags) if tags.len() == view.notes_num() => tags.iter().enumerate()
+            .any(|(i, tag)| ecdh_x(view.note_a_pub(i)).map(|x| detection_tag(x) == *tag).unwrap_or(false)),
+        _ => true
+    }
 }
 
-pub fn decrypt_in<P: PoolParams>(eta:Num<P::Fr>, memo:&[u8], params:&P)->Vec<Option<Note<P::Fr>>> {
-    if let Some(res) = _decrypt_in(eta, memo, params) {
-        res
-    } else {
-        vec![]
+// false only if memo has valid detection tags and none of the notes is addressed to eta.
+// It still takes one scalar multiplication per note, so foreign memos are rejected about
+// twice faster than by decrypt_in, not without ECDH. true doesn't mean there are notes for eta.
+pub fn quick_check<P: PoolParams>(eta:Num<P::Fr>, memo:&[u8], params:&P) -> bool {
+    match MemoView::parse(memo) {
+        Ok(view) => quick_check_view(&|x| ecdh_x_unchecked(x, eta, params), &view),
+        Err(_) => true
     }
 }
@@ -0,0 +1,66 @@
+use crate::{constants::ENERGY_SIZE_BITS, fawkes_crypto::ff_uint::PrimeField, native::{
+        account::Account,
+        note::Note,
+        tx::{Delta, DeltaError},
+    }};
+
+use std::fmt::{self, Display};
+use std::convert::TryFrom;
+
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EnergyError {
+    IndexOrder,
+    EnergyOutOfRange,
+    InsufficientEnergy,
+    InvalidDelta(DeltaError),
+}
+
+impl Display for EnergyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            EnergyError::IndexOrder => write!(f, "leaf index is greater than the current index"),
+            EnergyError::EnergyOutOfRange => write!(f, "energy is out of range"),
+            EnergyError::InsufficientEnergy => write!(f, "withdrawn energy is greater than the available energy"),
+            EnergyError::InvalidDelta(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for EnergyError {}
+
+
+// energy of the balance stored at the leaf index up to current_index, as c_transfer computes it
+pub fn accrued_energy(b:u64, index:u64, current_index:u64) -> Result<u128, EnergyError> {
+    if index > current_index {
+        Err(EnergyError::IndexOrder)
+    } else {
+        Ok(b as u128 * (current_index - index) as u128)
+    }
+}
+
+// energy of the output account before delta, when the account at account_index and notes
+// with their leaf indexes are spent at current_index
+pub fn total_energy<Fr:PrimeField>(account:&Account<Fr>, account_index:u64, notes:&[(u64, Note<Fr>)], current_index:u64) -> Result<u128, EnergyError> {
+    let account_energy = accrued_energy(u64::try_from(account.b.to_num()).unwrap(), account_index, current_index)?;
+    let res = notes.iter().try_fold(u128::try_from(account.e.to_num()).unwrap() + account_energy, |acc, (index, note)|
+        accrued_energy(u64::try_from(note.b.to_num()).unwrap(), *index, current_index)?
+            .checked_add(acc).ok_or(EnergyError::EnergyOutOfRange)
+    )?;
+
+    if res >> ENERGY_SIZE_BITS != 0 {
+        Err(EnergyError::EnergyOutOfRange)
+    } else {
+        Ok(res)
+    }
+}
+
+// delta withdrawing amount of value and energy of the transaction, available is total_energy of it
+pub fn energy_withdrawal_delta(available:u128, energy:u128, amount:u64, index:u64, pool_id:u32) -> Result<Delta, EnergyError> {
+    if energy > available {
+        return Err(EnergyError::InsufficientEnergy);
+    }
+    let energy = i128::try_from(energy).map_err(|_| EnergyError::EnergyOutOfRange)?;
+    let amount = i64::try_from(amount).map_err(|_| EnergyError::InvalidDelta(DeltaError::ValueOutOfRange))?;
+    Delta::new(-amount, -energy, index, pool_id).map_err(EnergyError::InvalidDelta)
+}
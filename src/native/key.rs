@@ -1,7 +1,39 @@
-use fawkes_crypto::ff_uint::PrimeField;
+use fawkes_crypto::ff_uint::{PrimeField, PrimeFieldParams, Uint};
 use fawkes_crypto::native::{ecc::{EdwardsPoint, JubJubParams}, poseidon::{poseidon}};
-use fawkes_crypto::ff_uint::Num;
-use crate::native::params::PoolParams;
+use fawkes_crypto::ff_uint::{Num, NumRepr};
+use fawkes_crypto::borsh::{self, BorshSerialize, BorshDeserialize};
+use crate::native::{
+    account::Account,
+    address::Address,
+    note::Note,
+    params::PoolParams,
+    cipher,
+    tx::{nullifier, tx_verify},
+};
+use crate::constants;
+
+use sha3::{Digest, Keccak512};
+
+
+const SIGMA_DOMAIN: &[u8] = b"ZeroPool/sigma";
+
+
+// spending key of the account with given index, derived from the wallet seed (64 bytes of BIP-39 seed)
+// sigma = keccak512(SIGMA_DOMAIN || u32_le(seed.len()) || seed || u32_le(account)) mod r,
+// 512 bit hash is interpreted as little endian integer, so the reduction bias is negligible
+pub fn derive_key_sigma<P:PoolParams>(seed:&[u8], account:u32) -> Num<P::Fs> {
+    let mut hasher = Keccak512::new();
+    hasher.update(SIGMA_DOMAIN);
+    hasher.update((seed.len() as u32).to_le_bytes());
+    hasher.update(seed);
+    hasher.update(account.to_le_bytes());
+    let h = hasher.finalize();
+
+    let lo = Num::<P::Fs>::from_uint_reduced(NumRepr::try_from_slice(&h[..32]).unwrap());
+    let hi = Num::<P::Fs>::from_uint_reduced(NumRepr::try_from_slice(&h[32..]).unwrap());
+    let shift = (0..256).fold(Num::<P::Fs>::ONE, |acc, _| acc.double());
+    hi * shift + lo
+}
 
 
 // intermediate key
@@ -17,6 +49,13 @@ pub fn derive_key_eta<P:PoolParams>(a: Num<P::Fr>, params: &P) -> Num<P::Fr> {
     poseidon(&[a], params.hash())
 }
 
+// outgoing viewing key, can't be derived from eta.
+// Second input is the domain constant, so ovk differs from merkle nodes and other hashes of a
+pub fn derive_key_ovk<P:PoolParams>(a: Num<P::Fr>, params: &P) -> Num<P::Fr> {
+    let domain = Num::from_uint_reduced(NumRepr(<P::Fr as PrimeFieldParams>::Inner::from_big_endian(&cipher::keccak256(constants::OVK_DOMAIN))));
+    poseidon(&[a, domain], params.compress())
+}
+
 
 pub fn derive_key_p_d<P:PoolParams, Fr:PrimeField>(
     d: Num<P::Fr>,
@@ -26,4 +65,108 @@ pub fn derive_key_p_d<P:PoolParams, Fr:PrimeField>(
     let eta_reduced = eta.to_other_reduced();
     let d_hash = poseidon(&[d], params.hash());
     EdwardsPoint::from_scalar(d_hash, params.jubjub()).mul(eta_reduced, params.jubjub())
-}
\ No newline at end of file
+}
+
+
+// Watch-only key: decrypts memos, computes nullifiers and verifies signatures of the account,
+// but can't sign transactions, because sigma is not known.
+// Deserialization checks a and eta with POOL_PARAMS, see native::borsh::key
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ViewingKey<Fr:PrimeField> {
+    pub(crate) a: Num<Fr>,
+    pub(crate) eta: Num<Fr>,
+}
+
+impl<Fr:PrimeField> ViewingKey<Fr> {
+    pub fn from_sigma<P:PoolParams<Fr=Fr>>(sigma:Num<P::Fs>, params:&P) -> Self {
+        let a = derive_key_a(sigma, params).x;
+        Self {a, eta: derive_key_eta(a, params)}
+    }
+
+    // a is x coordinate of derive_key_a(sigma)
+    pub fn new<P:PoolParams<Fr=Fr>>(a:Num<Fr>, params:&P) -> Option<Self> {
+        EdwardsPoint::subgroup_decompress(a, params.jubjub())?;
+        Some(Self {a, eta: derive_key_eta(a, params)})
+    }
+
+    pub fn a(&self) -> Num<Fr> {
+        self.a
+    }
+
+    pub fn eta(&self) -> Num<Fr> {
+        self.eta
+    }
+
+    pub fn ovk<P:PoolParams<Fr=Fr>>(&self, params:&P) -> Num<Fr> {
+        derive_key_ovk(self.a, params)
+    }
+
+    pub fn incoming(&self) -> IncomingViewingKey<Fr> {
+        IncomingViewingKey {eta: self.eta}
+    }
+
+    pub fn outgoing<P:PoolParams<Fr=Fr>>(&self, params:&P) -> OutgoingViewingKey<Fr> {
+        OutgoingViewingKey {ovk: self.ovk(params)}
+    }
+
+    pub fn is_own_address<P:PoolParams<Fr=Fr>>(&self, address:&Address<Fr>, params:&P) -> bool {
+        address.is_own(self.eta, params)
+    }
+
+    pub fn nullifier<P:PoolParams<Fr=Fr>>(&self, account_hash:Num<Fr>, index:Num<Fr>, params:&P) -> Num<Fr> {
+        nullifier(account_hash, self.eta, index, params)
+    }
+
+    pub fn verify<P:PoolParams<Fr=Fr>>(&self, s:Num<P::Fs>, r:Num<Fr>, tx_hash:Num<Fr>, params:&P) -> bool {
+        tx_verify(s, r, self.a, tx_hash, params)
+    }
+
+    // memos encrypted with ovk and legacy memos encrypted with eta
+    pub fn decrypt_out<P:PoolParams<Fr=Fr>>(&self, memo:&[u8], params:&P) -> Option<(Account<Fr>, Vec<Note<Fr>>)> {
+        self.outgoing(params).decrypt_out(memo, params).or_else(|| cipher::decrypt_out(self.eta, memo, params))
+    }
+
+    pub fn decrypt_in<P:PoolParams<Fr=Fr>>(&self, memo:&[u8], params:&P) -> Vec<Option<Note<Fr>>> {
+        cipher::decrypt_in(self.eta, memo, params)
+    }
+}
+
+
+// decrypts only notes addressed to the owner
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, BorshSerialize, BorshDeserialize)]
+#[serde(bound(serialize = "", deserialize = ""))]
+pub struct IncomingViewingKey<Fr:PrimeField> {
+    eta: Num<Fr>,
+}
+
+impl<Fr:PrimeField> IncomingViewingKey<Fr> {
+    pub fn eta(&self) -> Num<Fr> {
+        self.eta
+    }
+
+    pub fn is_own_address<P:PoolParams<Fr=Fr>>(&self, address:&Address<Fr>, params:&P) -> bool {
+        address.is_own(self.eta, params)
+    }
+
+    pub fn decrypt_in<P:PoolParams<Fr=Fr>>(&self, memo:&[u8], params:&P) -> Vec<Option<Note<Fr>>> {
+        cipher::decrypt_in(self.eta, memo, params)
+    }
+}
+
+
+// decrypts the sender account and outgoing notes of memos encrypted with ovk
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, BorshSerialize, BorshDeserialize)]
+#[serde(bound(serialize = "", deserialize = ""))]
+pub struct OutgoingViewingKey<Fr:PrimeField> {
+    ovk: Num<Fr>,
+}
+
+impl<Fr:PrimeField> OutgoingViewingKey<Fr> {
+    pub fn ovk(&self) -> Num<Fr> {
+        self.ovk
+    }
+
+    pub fn decrypt_out<P:PoolParams<Fr=Fr>>(&self, memo:&[u8], params:&P) -> Option<(Account<Fr>, Vec<Note<Fr>>)> {
+        cipher::decrypt_out_with_ovk(self.ovk, memo, params)
+    }
+}
@@ -6,6 +6,12 @@ pub mod account;
 pub mod cipher;
 pub mod key;
 pub mod tree;
+pub mod storage;
+pub mod address;
+pub mod scanner;
+pub mod wallet;
+pub mod planner;
+pub mod energy;
 
 mod borsh;
 mod sample;
@@ -14,6 +14,16 @@ pub struct Note<Fr:PrimeField> {
 }
 
 impl<Fr:PrimeField> Note<Fr> {
+    // padding of unused output slots
+    pub fn zero() -> Self {
+        Self {
+            d: BoundedNum::new(Num::ZERO),
+            p_d: Num::ZERO,
+            b: BoundedNum::new(Num::ZERO),
+            t: BoundedNum::new(Num::ZERO),
+        }
+    }
+
     pub fn hash<P:PoolParams<Fr=Fr>>(&self, params:&P) -> Num<Fr> {
         poseidon(&[self.d.to_num(), self.p_d, self.b.to_num(), self.t.to_num()], params.note())
     }
@@ -0,0 +1,65 @@
+use crate::{constants, fawkes_crypto::ff_uint::PrimeField, native::note::Note};
+
+use std::fmt::{self, Display};
+use std::convert::TryFrom;
+
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PlanError {
+    InsufficientFunds,
+}
+
+impl Display for PlanError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PlanError::InsufficientFunds => write!(f, "account balance and unspent notes are less than the target amount")
+        }
+    }
+}
+
+impl std::error::Error for PlanError {}
+
+
+// One transaction of the plan. Input notes are the oldest unspent notes and the output account
+// index is the index of the oldest note left, so c_transfer accepts the rest of notes later.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PlannedTx {
+    pub notes: Vec<u64>,
+    // None when no notes are left, the output account index is the current index then
+    pub output_index: Option<u64>,
+}
+
+// Every transaction spends up to IN oldest notes, because skipped notes older than the output
+// account index can't be spent anymore. Proof cost doesn't depend on the number of notes, so
+// each transaction takes as many notes as possible. Consolidation transactions are added while
+// the account balance is below the target, the last transaction has at least target balance.
+// Only the first transaction can be built at once, the next one spends the output account of the previous.
+pub fn plan_inputs<Fr:PrimeField>(balance:u64, notes:&[(u64, Note<Fr>)], target:u64) -> Result<Vec<PlannedTx>, PlanError> {
+    plan_inputs_with_shape::<Fr, { constants::IN }>(balance, notes, target)
+}
+
+// plan for the circuit with IN input notes, plan_inputs uses the default shape
+pub fn plan_inputs_with_shape<Fr:PrimeField, const IN: usize>(balance:u64, notes:&[(u64, Note<Fr>)], target:u64) -> Result<Vec<PlannedTx>, PlanError> {
+    let mut notes = notes.iter().map(|(i, n)| (*i, u64::try_from(n.b.to_num()).unwrap())).collect::<Vec<_>>();
+    notes.sort_unstable();
+
+    let mut balance = balance as u128;
+    if balance + notes.iter().map(|&(_, b)| b as u128).sum::<u128>() < target as u128 {
+        return Err(PlanError::InsufficientFunds);
+    }
+
+    let mut res = vec![];
+    let mut rest = &notes[..];
+    loop {
+        let (inputs, tail) = rest.split_at(rest.len().min(IN));
+        balance += inputs.iter().map(|&(_, b)| b as u128).sum::<u128>();
+        res.push(PlannedTx {
+            notes: inputs.iter().map(|&(i, _)| i).collect(),
+            output_index: tail.first().map(|&(i, _)| i),
+        });
+        if balance >= target as u128 || tail.is_empty() {
+            return Ok(res);
+        }
+        rest = tail;
+    }
+}
@@ -0,0 +1,201 @@
+use crate::{constants, fawkes_crypto::{
+        native::ecc::{EdwardsPoint, EdwardsPointEx, JubJubParams},
+        ff_uint::{Num, NumRepr, PrimeField, Uint},
+        borsh::BorshSerialize,
+    }, native::{
+        params::PoolParams,
+        account::Account,
+        note::Note,
+        key::ViewingKey,
+        cipher::{self, keccak256, MemoError, MemoView},
+    }};
+
+#[cfg(feature="rayon")]
+use rayon::prelude::*;
+
+
+const WNAF_WINDOW: u32 = 4;
+
+// Width-4 NAF recoding of a fixed scalar, little endian digits are odd numbers in [-7, 7] or zero.
+// Multiplication by it needs one addition per 5 bits on average instead of one per 2 bits.
+#[derive(Clone, Debug)]
+pub struct Wnaf(Vec<i8>);
+
+impl Wnaf {
+    pub fn new<U:Uint>(mut n:NumRepr<U>) -> Self {
+        let mut res = vec![];
+        while n != NumRepr::ZERO {
+            if n.is_odd() {
+                let d = (n.0.low_u64() & ((1 << WNAF_WINDOW) - 1)) as i8;
+                if d >= 1 << (WNAF_WINDOW - 1) {
+                    let d = d - (1 << WNAF_WINDOW);
+                    n += NumRepr::from((-d) as u64);
+                    res.push(d);
+                } else {
+                    n -= NumRepr::from(d as u64);
+                    res.push(d);
+                }
+            } else {
+                res.push(0);
+            }
+            n >>= 1;
+        }
+        Self(res)
+    }
+
+    // same as EdwardsPointEx::mul by the recoded scalar
+    pub fn mul<J:JubJubParams>(&self, p:&EdwardsPointEx<J::Fr>, params:&J) -> EdwardsPointEx<J::Fr> {
+        // odd multiples p, 3p, 5p, 7p
+        let p2 = p.double();
+        let mut table = vec![*p];
+        for i in 1..1 << (WNAF_WINDOW - 2) {
+            table.push(table[i - 1].add(&p2, params));
+        }
+
+        let mut res = EdwardsPointEx::zero();
+        for &d in self.0.iter().rev() {
+            res = res.double();
+            if d > 0 {
+                res = res.add(&table[(d / 2) as usize], params);
+            } else if d < 0 {
+                res = res.add(&table[(-d / 2) as usize].negate(), params);
+            }
+        }
+        res
+    }
+}
+
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ScannedMemo<Fr:PrimeField> {
+    pub index: usize,
+    // sender account and all output notes, when the memo is sent by the owner of the key
+    pub out: Option<(Account<Fr>, Vec<Note<Fr>>)>,
+    // notes addressed to the owner of the key with their positions in the memo
+    pub in_notes: Vec<(usize, Note<Fr>)>,
+}
+
+
+// Scans many memos with one key. The keys and the subgroup order are recoded once, each memo is
+// parsed once and all decryption passes share it. With rayon feature memos are scanned in parallel.
+#[derive(Clone, Debug)]
+pub struct MemoScanner<Fr:PrimeField> {
+    eta: Wnaf,
+    ovk: Option<Wnaf>,
+    // subgroup order for the check of decompressed points
+    modulus: Wnaf,
+    _fr: std::marker::PhantomData<Fr>,
+}
+
+impl<Fr:PrimeField> MemoScanner<Fr> {
+    // scans incoming notes and outgoing data of legacy memos
+    pub fn new<P:PoolParams<Fr=Fr>>(eta:Num<Fr>, _params:&P) -> Self {
+        Self {
+            eta: Wnaf::new(eta.to_other_reduced::<P::Fs>().to_uint()),
+            ovk: None,
+            modulus: Wnaf::new(Num::<P::Fs>::MODULUS),
+            _fr: std::marker::PhantomData,
+        }
+    }
+
+    pub fn from_viewing_key<P:PoolParams<Fr=Fr>>(vk:&ViewingKey<Fr>, params:&P) -> Self {
+        Self {
+            ovk: Some(Wnaf::new(vk.ovk(params).to_other_reduced::<P::Fs>().to_uint())),
+            ..Self::new(vk.eta(), params)
+        }
+    }
+
+    // any of two points with x coordinate
+    fn decompress<P:PoolParams<Fr=Fr>>(x:Num<Fr>, params:&P) -> Result<EdwardsPoint<Fr>, MemoError> {
+        let x2 = x.square();
+        let y = ((x2 + Num::ONE) / (Num::ONE - params.jubjub().edwards_d() * x2)).sqrt().ok_or(MemoError::InvalidPoint)?;
+        Ok(EdwardsPoint {x, y})
+    }
+
+    // same as EdwardsPoint::subgroup_decompress
+    fn subgroup_decompress<P:PoolParams<Fr=Fr>>(&self, x:Num<Fr>, params:&P) -> Result<EdwardsPointEx<Fr>, MemoError> {
+        let p = Self::decompress(x, params)?;
+        let l = self.modulus.mul(&p.into_extended(), params.jubjub()).into_affine();
+        if !l.x.is_zero() {
+            Err(MemoError::InvalidPoint)
+        } else if l.y == Num::ONE {
+            Ok(p.into_extended())
+        } else {
+            Ok(EdwardsPoint {x, y: -p.y}.into_extended())
+        }
+    }
+
+    // same as cipher::ecdh_key
+    fn ecdh_key<P:PoolParams<Fr=Fr>>(&self, key:&Wnaf, x:Num<Fr>, params:&P) -> Result<[u8;constants::U256_SIZE], MemoError> {
+        let p = self.subgroup_decompress(x, params)?;
+        let ecdh = key.mul(&p, params.jubjub()).into_affine();
+        Ok(keccak256(&ecdh.x.try_to_vec().unwrap()))
+    }
+
+    // same as cipher::ecdh_x_unchecked
+    fn ecdh_x_unchecked<P:PoolParams<Fr=Fr>>(&self, x:Num<Fr>, params:&P) -> Result<Num<Fr>, MemoError> {
+        let p = Self::decompress(x, params)?;
+        Ok(self.eta.mul(&p.into_extended(), params.jubjub()).into_affine().x)
+    }
+
+    // same as cipher::quick_check
+    pub fn quick_check<P:PoolParams<Fr=Fr>>(&self, memo:&[u8], params:&P) -> bool {
+        match MemoView::parse(memo) {
+            Ok(view) => self.quick_check_view(&view, params),
+            Err(_) => true
+        }
+    }
+
+    pub fn decrypt_out<P:PoolParams<Fr=Fr>>(&self, memo:&[u8], params:&P) -> Result<(Account<Fr>, Vec<Note<Fr>>), MemoError> {
+        self.decrypt_out_view(&MemoView::parse(memo)?, params)
+    }
+
+    pub fn decrypt_in<P:PoolParams<Fr=Fr>>(&self, memo:&[u8], params:&P) -> Result<Vec<Option<Note<Fr>>>, MemoError> {
+        self.decrypt_in_view(&MemoView::parse(memo)?, params)
+    }
+
+    fn quick_check_view<P:PoolParams<Fr=Fr>>(&self, view:&MemoView<Fr>, params:&P) -> bool {
+        cipher::quick_check_view(&|x| self.ecdh_x_unchecked(x, params), view)
+    }
+
+    fn decrypt_out_view<P:PoolParams<Fr=Fr>>(&self, view:&MemoView<Fr>, params:&P) -> Result<(Account<Fr>, Vec<Note<Fr>>), MemoError> {
+        let key = match view.header().version {
+            constants::MEMO_VERSION_LEGACY => &self.eta,
+            _ => self.ovk.as_ref().ok_or(MemoError::VersionMismatch)?,
+        };
+        cipher::decrypt_out_view(&|x| self.ecdh_key(key, x, params), view, params)
+    }
+
+    fn decrypt_in_view<P:PoolParams<Fr=Fr>>(&self, view:&MemoView<Fr>, params:&P) -> Result<Vec<Option<Note<Fr>>>, MemoError> {
+        cipher::decrypt_in_view(&|x| self.ecdh_key(&self.eta, x, params), view, params).into_iter().collect()
+    }
+
+    // memo is parsed once for all passes
+    fn scan_one<P:PoolParams<Fr=Fr>>(&self, index:usize, memo:&[u8], params:&P) -> Option<ScannedMemo<Fr>> {
+        let view = MemoView::parse(memo).ok()?;
+        let out = self.decrypt_out_view(&view, params).ok();
+        let in_notes = if self.quick_check_view(&view, params) {
+            self.decrypt_in_view(&view, params).unwrap_or_default()
+                .into_iter().enumerate().filter_map(|(i, n)| n.map(|n| (i, n))).collect::<Vec<_>>()
+        } else {
+            vec![]
+        };
+
+        if out.is_none() && in_notes.is_empty() {
+            None
+        } else {
+            Some(ScannedMemo {index, out, in_notes})
+        }
+    }
+
+    // returns only memos with decrypted data, index is the position in memos slice
+    #[cfg(not(feature="rayon"))]
+    pub fn scan<M:AsRef<[u8]>+Sync, P:PoolParams<Fr=Fr>+Sync>(&self, memos:&[M], params:&P) -> Vec<ScannedMemo<Fr>> where Fr:Send+Sync {
+        memos.iter().enumerate().filter_map(|(i, m)| self.scan_one(i, m.as_ref(), params)).collect()
+    }
+
+    #[cfg(feature="rayon")]
+    pub fn scan<M:AsRef<[u8]>+Sync, P:PoolParams<Fr=Fr>+Sync>(&self, memos:&[M], params:&P) -> Vec<ScannedMemo<Fr>> where Fr:Send+Sync {
+        memos.par_iter().enumerate().filter_map(|(i, m)| self.scan_one(i, m.as_ref(), params)).collect()
+    }
+}
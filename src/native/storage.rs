@@ -0,0 +1,177 @@
+use crate::{constants, fawkes_crypto::{
+    ff_uint::{Num, PrimeField},
+    borsh::{BorshSerialize, BorshDeserialize},
+}, native::cipher::keccak256};
+
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+
+// u32 length, its checksum and keccak256 of the record body
+const RECORD_HEADER_SIZE:usize = 8 + constants::U256_SIZE;
+
+// (height, index, value), None value removes the node
+pub type TreeNodeUpdate<Fr> = (usize, u64, Option<Num<Fr>>);
+
+// Storage of non-default nodes of the merkle tree
+pub trait TreeStorage<Fr:PrimeField> {
+    fn get(&self, height:usize, index:u64) -> Option<Num<Fr>>;
+
+    fn next_index(&self) -> u64;
+
+    // all updates and the new leaf count should be applied atomically
+    fn write_batch(&mut self, next_index:u64, nodes:&[TreeNodeUpdate<Fr>]) -> io::Result<()>;
+
+    fn put(&mut self, height:usize, index:u64, value:Num<Fr>) -> io::Result<()> {
+        self.write_batch(self.next_index(), &[(height, index, Some(value))])
+    }
+}
+
+
+#[derive(Clone)]
+pub struct MemoryStorage<Fr:PrimeField> {
+    nodes: HashMap<(usize, u64), Num<Fr>>,
+    next_index: u64
+}
+
+impl<Fr:PrimeField> MemoryStorage<Fr> {
+    pub fn new() -> Self {
+        Self {
+            nodes: HashMap::new(),
+            next_index: 0
+        }
+    }
+
+    fn apply(&mut self, next_index:u64, nodes:&[TreeNodeUpdate<Fr>]) {
+        for &(height, index, value) in nodes.iter() {
+            match value {
+                Some(v) => self.nodes.insert((height, index), v),
+                None => self.nodes.remove(&(height, index))
+            };
+        }
+        self.next_index = next_index;
+    }
+}
+
+impl<Fr:PrimeField> Default for MemoryStorage<Fr> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<Fr:PrimeField> TreeStorage<Fr> for MemoryStorage<Fr> {
+    fn get(&self, height:usize, index:u64) -> Option<Num<Fr>> {
+        self.nodes.get(&(height, index)).cloned()
+    }
+
+    fn next_index(&self) -> u64 {
+        self.next_index
+    }
+
+    fn write_batch(&mut self, next_index:u64, nodes:&[TreeNodeUpdate<Fr>]) -> io::Result<()> {
+        self.apply(next_index, nodes);
+        Ok(())
+    }
+}
+
+
+// Append-only log of batches, each record is u32 length, first 4 bytes of keccak256 of the length,
+// keccak256 of the body and the body, borsh encoded (next_index, nodes). The whole log is replayed
+// into memory on open. Broken record at the end of the file is left by an interrupted write and
+// discarded, any other broken record is reported as InvalidData.
+pub struct FileStorage<Fr:PrimeField> {
+    file: File,
+    // length of the complete records, the file is cut back to it after a failed write
+    len: u64,
+    // the file couldn't be cut back, its tail is unknown
+    failed: bool,
+    state: MemoryStorage<Fr>
+}
+
+impl<Fr:PrimeField> FileStorage<Fr> {
+    pub fn open<T:AsRef<Path>>(path:T) -> io::Result<Self> {
+        let mut file = OpenOptions::new().read(true).write(true).create(true).truncate(false).open(path)?;
+        let mut data = vec![];
+        file.read_to_end(&mut data)?;
+
+        let mut state = MemoryStorage::new();
+        let mut buf = &data[..];
+        while let Some((next_index, nodes)) = Self::read_record(&mut buf)? {
+            state.apply(next_index, &nodes);
+        }
+
+        let valid_len = (data.len() - buf.len()) as u64;
+        if !buf.is_empty() {
+            file.set_len(valid_len)?;
+            file.sync_data()?;
+        }
+        file.seek(SeekFrom::Start(valid_len))?;
+
+        Ok(Self {file, len: valid_len, failed: false, state})
+    }
+
+    // None if buf is empty or holds a broken last record
+    fn read_record(buf:&mut &[u8]) -> io::Result<Option<(u64, Vec<TreeNodeUpdate<Fr>>)>> {
+        let invalid = |msg| io::Error::new(io::ErrorKind::InvalidData, msg);
+        if buf.len() < RECORD_HEADER_SIZE {
+            return Ok(None);
+        }
+        let (len_bytes, b) = buf.split_at(4);
+        let (len_checksum, b) = b.split_at(4);
+        let (checksum, b) = b.split_at(constants::U256_SIZE);
+        if len_checksum != &keccak256(len_bytes)[..4] {
+            return Err(invalid("record length is corrupted"));
+        }
+        let len = u32::try_from_slice(len_bytes)? as usize;
+        // only the last record can be cut or broken by an interrupted write
+        if b.len() < len {
+            return Ok(None);
+        }
+        if checksum != keccak256(&b[..len]) {
+            return if b.len() == len { Ok(None) } else { Err(invalid("record checksum mismatch")) };
+        }
+        let (next_index, nodes) = <(u64, Vec<(u32, u64, Option<Num<Fr>>)>)>::try_from_slice(&b[..len])
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        *buf = &b[len..];
+        Ok(Some((next_index, nodes.into_iter().map(|(h, i, v)| (h as usize, i, v)).collect())))
+    }
+}
+
+impl<Fr:PrimeField> TreeStorage<Fr> for FileStorage<Fr> {
+    fn get(&self, height:usize, index:u64) -> Option<Num<Fr>> {
+        self.state.get(height, index)
+    }
+
+    fn next_index(&self) -> u64 {
+        self.state.next_index()
+    }
+
+    fn write_batch(&mut self, next_index:u64, nodes:&[TreeNodeUpdate<Fr>]) -> io::Result<()> {
+        let mut record = vec![];
+        next_index.serialize(&mut record)?;
+        nodes.iter().map(|&(h, i, v)| (h as u32, i, v)).collect::<Vec<_>>().serialize(&mut record)?;
+
+        let mut data = vec![];
+        (record.len() as u32).serialize(&mut data)?;
+        data.extend_from_slice(&keccak256(&data)[..4]);
+        data.extend_from_slice(&keccak256(&record));
+        data.extend(record);
+        if self.failed {
+            return Err(io::Error::other("storage is failed after an unrecoverable write error"));
+        }
+        if let Err(e) = self.file.write_all(&data).and_then(|_| self.file.sync_data()) {
+            // drop the partial record, otherwise the next record is appended after it
+            let len = self.len;
+            if self.file.set_len(len).and_then(|_| self.file.seek(SeekFrom::Start(len))).is_err() {
+                self.failed = true;
+            }
+            return Err(e);
+        }
+        self.len += data.len() as u64;
+
+        self.state.apply(next_index, nodes);
+        Ok(())
+    }
+}
@@ -1,14 +1,17 @@
 use crate::{
     fawkes_crypto::{
-        native::poseidon::MerkleProof,
+        native::poseidon::{poseidon, poseidon_merkle_proof_root, MerkleProof},
         ff_uint::{Num, PrimeField},
         borsh::{self, BorshSerialize, BorshDeserialize},
     },
+    native::{params::PoolParams, storage::{TreeStorage, MemoryStorage}},
     constants::{HEIGHT, OUTPLUSONELOG}
 };
 
 
-use std::fmt::Debug;
+use std::fmt::{self, Debug, Display};
+use std::collections::BTreeMap;
+use std::io;
 
 
 #[derive(Clone, Debug, Serialize, Deserialize, BorshSerialize, BorshDeserialize)]
@@ -19,10 +22,277 @@ pub struct TreePub<Fr:PrimeField> {
     pub leaf: Num<Fr>
 }
 
+// L is the height of the tree of commitments, tree height minus OUTPLUSONELOG
 #[derive(Clone, Debug, Serialize, Deserialize, BorshSerialize, BorshDeserialize)]
 #[serde(bound(serialize = "", deserialize = ""))]
-pub struct TreeSec<Fr:PrimeField> {
-    pub proof_filled:MerkleProof<Fr, {HEIGHT - OUTPLUSONELOG}>,
-    pub proof_free:MerkleProof<Fr, {HEIGHT - OUTPLUSONELOG}>,
+pub struct TreeSec<Fr:PrimeField, const L: usize = { HEIGHT - OUTPLUSONELOG }> {
+    pub proof_filled:MerkleProof<Fr, L>,
+    pub proof_free:MerkleProof<Fr, L>,
     pub prev_leaf:Num<Fr>
-}
\ No newline at end of file
+}
+
+
+// Sparse merkle tree of the pool. Leaves are account and note hashes, every transaction
+// occupies OUT+1 leaves, so out_commit of the transaction is the node at height OUTPLUSONELOG.
+// OUT is constants::OUT.
+// Only nodes that differ from the default (empty subtree) hashes are stored.
+// H is the tree height up to HEIGHT and L = H - OUTPLUSONELOG is the height of the tree of commitments,
+// stable Rust can't compute one from another in const generics.
+#[derive(Clone)]
+pub struct MerkleTree<P:PoolParams, S:TreeStorage<P::Fr> = MemoryStorage<<P as PoolParams>::Fr>, const H: usize = { HEIGHT }, const L: usize = { HEIGHT - OUTPLUSONELOG }> {
+    storage: S,
+    default_hashes: Vec<Num<P::Fr>>
+}
+
+impl<P:PoolParams> MerkleTree<P, MemoryStorage<P::Fr>> {
+    pub fn new(params:&P) -> Self {
+        Self::new_with_storage(MemoryStorage::new(), params)
+    }
+}
+
+impl<P:PoolParams, S:TreeStorage<P::Fr>> MerkleTree<P, S> {
+    pub fn new_with_storage(storage:S, params:&P) -> Self {
+        Self::new_with_height(storage, params)
+    }
+}
+
+impl<P:PoolParams, S:TreeStorage<P::Fr>, const H: usize, const L: usize> MerkleTree<P, S, H, L> {
+    // tree of height H, new and new_with_storage use the default HEIGHT
+    pub fn new_with_height(storage:S, params:&P) -> Self {
+        assert!(H <= HEIGHT && L + OUTPLUSONELOG == H, "tree height is out of range or L != H - OUTPLUSONELOG");
+        let default_hashes = std::iter::successors(Some(Num::ZERO), |t|
+            Some(poseidon([*t,*t].as_ref(), params.compress()))
+        ).take(H+1).collect();
+
+        Self {
+            storage,
+            default_hashes
+        }
+    }
+
+    pub fn storage(&self) -> &S {
+        &self.storage
+    }
+
+    // number of leaves, including the padding of transactions appended by commitment
+    pub fn next_index(&self) -> u64 {
+        self.storage.next_index()
+    }
+
+    pub fn default_hash(&self, height:usize) -> Num<P::Fr> {
+        self.default_hashes[height]
+    }
+
+    pub fn node(&self, height:usize, index:u64) -> Num<P::Fr> {
+        self.storage.get(height, index).unwrap_or(self.default_hashes[height])
+    }
+
+    pub fn root(&self) -> Num<P::Fr> {
+        self.node(H, 0)
+    }
+
+    // append one leaf, returns its index
+    pub fn append(&mut self, leaf:Num<P::Fr>, params:&P) -> io::Result<u64> {
+        let index = self.next_index();
+        let mut batch = BTreeMap::new();
+        self.update_path(&mut batch, 0, index, leaf, params);
+        self.write_batch(index + 1, batch)?;
+        Ok(index)
+    }
+
+    // append out_commit of a transaction into the next free subtree of OUT+1 leaves, returns the commitment index.
+    // Leaves of this subtree are unknown to the tree, so merkle_proof for them is not valid.
+    pub fn append_commitment(&mut self, commitment:Num<P::Fr>, params:&P) -> io::Result<u64> {
+        let index = Self::commitment_index_ceil(self.next_index());
+        let mut batch = BTreeMap::new();
+        self.update_path(&mut batch, OUTPLUSONELOG, index, commitment, params);
+        self.write_batch((index + 1) << OUTPLUSONELOG, batch)?;
+        Ok(index)
+    }
+
+    // index of the first commitment subtree, which is not touched by leaves before next_index
+    fn commitment_index_ceil(next_index:u64) -> u64 {
+        (next_index + (1 << OUTPLUSONELOG) - 1) >> OUTPLUSONELOG
+    }
+
+    pub fn merkle_proof(&self, index:u64) -> MerkleProof<P::Fr, H> {
+        self.proof(0, index)
+    }
+
+    // proof for the node at height OUTPLUSONELOG, as used in TreeSec
+    pub fn commitment_proof(&self, index:u64) -> MerkleProof<P::Fr, L> {
+        self.proof(OUTPLUSONELOG, index)
+    }
+
+    // drop all leaves with index >= next_index and restore the state of the tree at that moment.
+    // Leaves of a transaction appended by commitment are unknown, so next_index can't point inside it.
+    pub fn rollback(&mut self, next_index:u64, params:&P) -> io::Result<()> {
+        let prev_next_index = self.next_index();
+        if next_index > prev_next_index {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "rollback index is beyond the tree"));
+        }
+        let commitment_index = next_index >> OUTPLUSONELOG;
+        if next_index & ((1 << OUTPLUSONELOG) - 1) != 0 && self.storage.get(0, commitment_index << OUTPLUSONELOG).is_none()
+            && self.storage.get(OUTPLUSONELOG, commitment_index).is_some() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "rollback index is inside a transaction appended by commitment"));
+        }
+
+        let mut batch = BTreeMap::new();
+        for height in 0..H+1 {
+            let ceil = |n:u64| (n + (1 << height) - 1) >> height;
+            for index in ceil(next_index)..ceil(prev_next_index) {
+                if self.storage.get(height, index).is_some() {
+                    batch.insert((height, index), None);
+                }
+            }
+        }
+
+        if next_index > 0 {
+            // node containing the last leaf is complete at this height, all above should be recomputed
+            let height = (next_index.trailing_zeros() as usize).min(H);
+            let index = (next_index - 1) >> height;
+            let value = self.batch_node(&batch, height, index);
+            self.update_path(&mut batch, height, index, value, params);
+        }
+
+        self.write_batch(next_index, batch)
+    }
+
+    fn proof<const N: usize>(&self, height:usize, index:u64) -> MerkleProof<P::Fr, N> {
+        let sibling = (0..N).map(|i| self.node(height + i, (index >> i) ^ 1)).collect();
+        let path = (0..N).map(|i| (index >> i) & 1 == 1).collect();
+        MerkleProof {sibling, path}
+    }
+
+    fn batch_node(&self, batch:&BTreeMap<(usize, u64), Option<Num<P::Fr>>>, height:usize, index:u64) -> Num<P::Fr> {
+        match batch.get(&(height, index)) {
+            Some(v) => v.unwrap_or(self.default_hashes[height]),
+            None => self.node(height, index)
+        }
+    }
+
+    fn update_path(&self, batch:&mut BTreeMap<(usize, u64), Option<Num<P::Fr>>>, height:usize, mut index:u64, mut value:Num<P::Fr>, params:&P) {
+        batch.insert((height, index), Some(value));
+        for h in height..H {
+            let sibling = self.batch_node(batch, h, index ^ 1);
+            let pair = if index & 1 == 0 { [value, sibling] } else { [sibling, value] };
+            value = poseidon(pair.as_ref(), params.compress());
+            index >>= 1;
+            batch.insert((h + 1, index), Some(value));
+        }
+    }
+
+    fn write_batch(&mut self, next_index:u64, batch:BTreeMap<(usize, u64), Option<Num<P::Fr>>>) -> io::Result<()> {
+        let nodes = batch.into_iter().map(|((height, index), value)| {
+            let value = value.filter(|v| *v != self.default_hashes[height]);
+            (height, index, value)
+        }).filter(|&(height, index, value)| value.is_some() || self.storage.get(height, index).is_some())
+        .collect::<Vec<_>>();
+        self.storage.write_batch(next_index, &nodes)
+    }
+}
+
+// append out_commit of a transaction to the tree and build the witness for tree_update circuit
+pub fn make_tree_update<Fr:PrimeField, P:PoolParams<Fr=Fr>, S:TreeStorage<Fr>, const H: usize, const L: usize>(
+    tree: &mut MerkleTree<P, S, H, L>,
+    leaf: Num<Fr>,
+    params: &P
+) -> io::Result<(TreePub<Fr>, TreeSec<Fr, L>)> {
+    let root_before = tree.root();
+    let index_free = MerkleTree::<P, S, H, L>::commitment_index_ceil(tree.next_index());
+
+    //for empty tree previous leaf is not checked, index_free is zero
+    let (proof_filled, prev_leaf) = if index_free == 0 {
+        (tree.commitment_proof(0), Num::ZERO)
+    } else {
+        (tree.commitment_proof(index_free - 1), tree.node(OUTPLUSONELOG, index_free - 1))
+    };
+    let proof_free = tree.commitment_proof(index_free);
+
+    tree.append_commitment(leaf, params)?;
+    let root_after = tree.root();
+
+    let p = TreePub {root_before, root_after, leaf};
+    let s = TreeSec {proof_filled, proof_free, prev_leaf};
+    Ok((p, s))
+}
+
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TreeUpdateError {
+    ZeroLeafProof,
+    RootAfterProof,
+    PrevLeafProof,
+    PrevLeafIndex,
+    PrevLeafZero,
+    EmptyTreeIndex,
+}
+
+impl Display for TreeUpdateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let msg = match self {
+            TreeUpdateError::ZeroLeafProof => "free leaf proof is not valid for zero leaf and root_before",
+            TreeUpdateError::RootAfterProof => "free leaf proof is not valid for the new leaf and root_after",
+            TreeUpdateError::PrevLeafProof => "filled leaf proof is not valid for prev_leaf and root_before",
+            TreeUpdateError::PrevLeafIndex => "filled leaf is not adjacent to the free leaf",
+            TreeUpdateError::PrevLeafZero => "prev_leaf is zero in non-empty tree",
+            TreeUpdateError::EmptyTreeIndex => "free leaf index is nonzero in empty tree",
+        };
+        write!(f, "{}", msg)
+    }
+}
+
+impl std::error::Error for TreeUpdateError {}
+
+
+// native version of circuit::tree::tree_update
+pub fn tree_update_check<P:PoolParams, const L: usize>(
+    p: &TreePub<P::Fr>,
+    s: &TreeSec<P::Fr, L>,
+    params: &P
+) -> Result<(), TreeUpdateError> {
+    fn proof_index<const L: usize, Fr:PrimeField>(proof:&MerkleProof<Fr, L>) -> u64 {
+        proof.path.iter().rev().fold(0, |acc, &b| (acc << 1) + b as u64)
+    }
+
+    let mut zero_leaf_value = Num::ZERO;
+    for _ in 0..OUTPLUSONELOG {
+        zero_leaf_value = poseidon(&[zero_leaf_value, zero_leaf_value], params.compress());
+    }
+
+    let mut zero_root_value = Num::ZERO;
+    for _ in 0..L + OUTPLUSONELOG {
+        zero_root_value = poseidon(&[zero_root_value, zero_root_value], params.compress());
+    }
+
+    if poseidon_merkle_proof_root(zero_leaf_value, &s.proof_free, params.compress()) != p.root_before {
+        return Err(TreeUpdateError::ZeroLeafProof);
+    }
+
+    if poseidon_merkle_proof_root(p.leaf, &s.proof_free, params.compress()) != p.root_after {
+        return Err(TreeUpdateError::RootAfterProof);
+    }
+
+    let index_filled = proof_index(&s.proof_filled);
+    let index_free = proof_index(&s.proof_free);
+
+    if p.root_before == zero_root_value {
+        //for empty tree index_free should be zero
+        if index_free != 0 {
+            return Err(TreeUpdateError::EmptyTreeIndex);
+        }
+    } else {
+        //for non-empty tree previous proof should be valid for nonzero leaf
+        if poseidon_merkle_proof_root(s.prev_leaf, &s.proof_filled, params.compress()) != p.root_before {
+            return Err(TreeUpdateError::PrevLeafProof);
+        }
+        if index_filled + 1 != index_free {
+            return Err(TreeUpdateError::PrevLeafIndex);
+        }
+        if s.prev_leaf == zero_leaf_value {
+            return Err(TreeUpdateError::PrevLeafZero);
+        }
+    }
+
+    Ok(())
+}
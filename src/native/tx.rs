@@ -1,25 +1,33 @@
-use crate::{constants::{BALANCE_SIZE_BITS, ENERGY_SIZE_BITS, HEIGHT, IN, OUT, POOLID_SIZE_BITS}, fawkes_crypto::{
+use crate::{constants::{self, BALANCE_SIZE_BITS, DIVERSIFIER_SIZE_BITS, ENERGY_SIZE_BITS, HEIGHT, POOLID_SIZE_BITS}, fawkes_crypto::{
         native::{
             eddsaposeidon::{eddsaposeidon_sign, eddsaposeidon_verify},
-            poseidon::{poseidon, poseidon_merkle_tree_root, poseidon_sponge, MerkleProof},
+            poseidon::{poseidon, poseidon_merkle_proof_root, poseidon_merkle_tree_root, poseidon_sponge, MerkleProof},
         },
         core::sizedvec::SizedVec,
         ff_uint::{Num, NumRepr, PrimeField, Uint},
         borsh::{self, BorshSerialize, BorshDeserialize},
+        rand::Rng,
     }, native::{
         params::PoolParams,
+        boundednum::BoundedNum,
         note::Note,
-        account::Account
+        account::Account,
+        address::Address,
+        key::{derive_key_a, derive_key_eta, derive_key_ovk, derive_key_p_d},
+        cipher::{self, keccak256, MemoHeader},
+        energy::total_energy,
     }};
 
 
-use std::fmt::Debug;
+use std::convert::TryFrom;
+use std::fmt::{self, Debug, Display};
 
 
 
 #[derive(Clone, Serialize, Deserialize, BorshSerialize, BorshDeserialize)]
 #[serde(bound(serialize = "", deserialize = ""))]
-pub struct Tx<Fr:PrimeField> {
+// IN and OUT are the circuit shape, by default constants::IN and constants::OUT
+pub struct Tx<Fr:PrimeField, const IN: usize = { constants::IN }, const OUT: usize = { constants::OUT }> {
     pub input: (Account<Fr>, SizedVec<Note<Fr>, { IN }>),
     pub output: (Account<Fr>, SizedVec<Note<Fr>, { OUT }>)
 }
@@ -36,9 +44,9 @@ pub struct TransferPub<Fr:PrimeField> {
 
 #[derive(Clone, Serialize, Deserialize)]
 #[serde(bound(serialize = "", deserialize = ""))]
-pub struct TransferSec<Fr:PrimeField> {
-    pub tx: Tx<Fr>,
-    pub in_proof: (MerkleProof<Fr, { HEIGHT }>, SizedVec<MerkleProof<Fr, { HEIGHT }>, { IN }>),
+pub struct TransferSec<Fr:PrimeField, const IN: usize = { constants::IN }, const OUT: usize = { constants::OUT }, const H: usize = { HEIGHT }> {
+    pub tx: Tx<Fr, IN, OUT>,
+    pub in_proof: (MerkleProof<Fr, H>, SizedVec<MerkleProof<Fr, H>, { IN }>),
     pub eddsa_s: Num<Fr>,
     pub eddsa_r: Num<Fr>,
     pub eddsa_a: Num<Fr>,
@@ -95,7 +103,15 @@ pub fn tx_verify<P:PoolParams>(
 }
 
 
-pub fn out_commitment_hash<P:PoolParams>(items:&[Num<P::Fr>], params: &P) -> Num<P::Fr> {
+// keccak256 of the whole memo as big endian integer reduced into the field, the same as
+// uint256(keccak256(memo)) % r on the contract side. Memo includes the header, so the fee
+// and extra data are bound too.
+pub fn memo_hash<Fr:PrimeField>(memo:&[u8]) -> Num<Fr> {
+    Num::from_uint_reduced(NumRepr(Fr::Inner::from_big_endian(&keccak256(memo))))
+}
+
+// items are the output account and OUT notes, OUT+1 should be a power of two
+pub fn out_commitment_hash<P:PoolParams, const OUT: usize>(items:&[Num<P::Fr>], params: &P) -> Num<P::Fr> {
     assert!(items.len()==OUT+1);
     poseidon_merkle_tree_root(items, params.compress())
 }
@@ -136,34 +152,597 @@ pub fn parse_delta<Fr:PrimeField>(delta: Num<Fr>) -> (Num<Fr>, Num<Fr>, Num<Fr>,
 }
 
 
-pub fn make_delta<Fr:PrimeField>(v:Num<Fr>, e:Num<Fr>, index:Num<Fr>, poolid:Num<Fr>) -> Num<Fr> {
-    fn make_uint<Fr:PrimeField>(s: &mut NumRepr<Fr::Inner>, n:Num<Fr>, len:usize) {
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DeltaError {
+    ValueOutOfRange,
+    EnergyOutOfRange,
+    IndexOutOfRange,
+    PoolIdOutOfRange,
+    BalanceOutOfRange,
+    InvalidDelta,
+}
+
+impl Display for DeltaError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let msg = match self {
+            DeltaError::ValueOutOfRange => "delta value is out of range",
+            DeltaError::EnergyOutOfRange => "delta energy is out of range",
+            DeltaError::IndexOutOfRange => "delta index is out of range",
+            DeltaError::PoolIdOutOfRange => "delta pool id is out of range",
+            DeltaError::BalanceOutOfRange => "account balance is negative or out of range",
+            DeltaError::InvalidDelta => "delta does not fit into its packed bit length",
+        };
+        write!(f, "{}", msg)
+    }
+}
+
+impl std::error::Error for DeltaError {}
+
+
+pub fn try_make_delta<Fr:PrimeField>(v:Num<Fr>, e:Num<Fr>, index:Num<Fr>, poolid:Num<Fr>) -> Result<Num<Fr>, DeltaError> {
+    fn make_uint<Fr:PrimeField>(s: &mut NumRepr<Fr::Inner>, n:Num<Fr>, len:usize) -> bool {
         let r = n.to_uint();
-        assert!(r >> len as u32 == NumRepr::ZERO, "out of range");
+        if r >> len as u32 != NumRepr::ZERO {
+            return false;
+        }
         *s = (*s << len as u32) + r;
+        true
     }
 
-    fn make_int<Fr:PrimeField>(s: &mut NumRepr<Fr::Inner>, n:Num<Fr>, len:usize) {
+    fn make_int<Fr:PrimeField>(s: &mut NumRepr<Fr::Inner>, n:Num<Fr>, len:usize) -> bool {
         let mut r = n.to_uint();
         if r >> (len as u32 - 1) == NumRepr::ZERO {
             *s = (*s << len as u32) + r;
-            return;
+            return true;
         }
         r = Num::<Fr>::MODULUS - r;
         if (r - NumRepr::ONE) >> (len as u32 - 1) == NumRepr::ZERO {
             r = (NumRepr::ONE << len as u32) - r;
             *s = (*s << len as u32) + r;
-            return;
+            return true;
         }
-        
-        panic!("out of range");
+        false
     }
 
     let mut s = NumRepr::ZERO;
-    make_uint(&mut s, poolid, POOLID_SIZE_BITS);
-    make_uint(&mut s, index, HEIGHT);
-    make_int(&mut s, e, ENERGY_SIZE_BITS);
-    make_int(&mut s, v, BALANCE_SIZE_BITS);
+    if !make_uint(&mut s, poolid, POOLID_SIZE_BITS) {
+        return Err(DeltaError::PoolIdOutOfRange);
+    }
+    if !make_uint(&mut s, index, HEIGHT) {
+        return Err(DeltaError::IndexOutOfRange);
+    }
+    if !make_int(&mut s, e, ENERGY_SIZE_BITS) {
+        return Err(DeltaError::EnergyOutOfRange);
+    }
+    if !make_int(&mut s, v, BALANCE_SIZE_BITS) {
+        return Err(DeltaError::ValueOutOfRange);
+    }
+
+    Ok(Num::from_uint(s).unwrap())
+}
+
+pub fn make_delta<Fr:PrimeField>(v:Num<Fr>, e:Num<Fr>, index:Num<Fr>, poolid:Num<Fr>) -> Num<Fr> {
+    try_make_delta(v, e, index, poolid).expect("out of range")
+}
+
+
+// Public part of the transaction from the pool point of view: deposit adds value to the pool,
+// withdrawal takes value and energy out of it, transfer only moves value between notes and accounts.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TxType {
+    Deposit { amount: u64 },
+    Transfer,
+    Withdraw { amount: u64, energy: u128 },
+}
+
+impl TxType {
+    // signed value and energy of delta
+    pub fn delta_value_energy<Fr:PrimeField>(&self) -> (Num<Fr>, Num<Fr>) {
+        match *self {
+            TxType::Deposit { amount } => (Num::from(amount), Num::ZERO),
+            TxType::Transfer => (Num::ZERO, Num::ZERO),
+            TxType::Withdraw { amount, energy } => (-Num::from(amount), -Num::from(energy)),
+        }
+    }
+
+    pub fn delta<Fr:PrimeField>(&self, index:Num<Fr>, poolid:Num<Fr>) -> Result<Num<Fr>, DeltaError> {
+        let (v, e) = self.delta_value_energy();
+        try_make_delta(v, e, index, poolid)
+    }
+
+    // balance of the output account, when input_value is spent from the input account and notes
+    // and output_value is sent to the output notes
+    pub fn output_balance<Fr:PrimeField>(&self, input_value:Num<Fr>, output_value:Num<Fr>) -> Result<BoundedNum<Fr, { BALANCE_SIZE_BITS }>, DeltaError> {
+        let (v, _) = self.delta_value_energy();
+        let b = input_value + v - output_value;
+        if b.to_uint() >> BALANCE_SIZE_BITS as u32 != NumRepr::ZERO {
+            Err(DeltaError::BalanceOutOfRange)
+        } else {
+            Ok(BoundedNum::new(b))
+        }
+    }
+}
+
+
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Delta {
+    pub(crate) value: i64,
+    pub(crate) energy: i128,
+    pub(crate) index: u64,
+    pub(crate) pool_id: u32,
+}
+
+impl Delta {
+    pub fn new(value:i64, energy:i128, index:u64, pool_id:u32) -> Result<Self, DeltaError> {
+        let energy_bound = 1i128 << (ENERGY_SIZE_BITS - 1);
+        if energy < -energy_bound || energy >= energy_bound {
+            return Err(DeltaError::EnergyOutOfRange);
+        }
+        if index >> HEIGHT != 0 {
+            return Err(DeltaError::IndexOutOfRange);
+        }
+        if pool_id >> POOLID_SIZE_BITS != 0 {
+            return Err(DeltaError::PoolIdOutOfRange);
+        }
+        Ok(Self {value, energy, index, pool_id})
+    }
+
+    pub fn value(&self) -> i64 {
+        self.value
+    }
+
+    pub fn energy(&self) -> i128 {
+        self.energy
+    }
+
+    pub fn index(&self) -> u64 {
+        self.index
+    }
+
+    pub fn pool_id(&self) -> u32 {
+        self.pool_id
+    }
+}
+
+impl<Fr:PrimeField> TryFrom<Num<Fr>> for Delta {
+    type Error = DeltaError;
+
+    fn try_from(delta: Num<Fr>) -> Result<Self, DeltaError> {
+        let len = BALANCE_SIZE_BITS + ENERGY_SIZE_BITS + HEIGHT + POOLID_SIZE_BITS;
+        if delta.to_uint() >> len as u32 != NumRepr::ZERO {
+            return Err(DeltaError::InvalidDelta);
+        }
+
+        let (v, e, index, pool_id) = parse_delta(delta);
+        let value = i128::try_from(v).map_err(|_| DeltaError::ValueOutOfRange)?;
+        Delta::new(
+            i64::try_from(value).map_err(|_| DeltaError::ValueOutOfRange)?,
+            i128::try_from(e).map_err(|_| DeltaError::EnergyOutOfRange)?,
+            u64::try_from(index).map_err(|_| DeltaError::IndexOutOfRange)?,
+            u32::try_from(pool_id).map_err(|_| DeltaError::PoolIdOutOfRange)?,
+        )
+    }
+}
+
+impl<Fr:PrimeField> From<Delta> for Num<Fr> {
+    fn from(delta: Delta) -> Self {
+        make_delta(
+            Num::from(delta.value as i128),
+            Num::from(delta.energy),
+            Num::from(delta.index),
+            Num::from(delta.pool_id as u64),
+        )
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TransferError {
+    InputNotesNotUnique,
+    OutputNotesNotUnique,
+    OutCommitMismatch,
+    InputAccountOwner,
+    OutputAccountOwner,
+    InputNoteOwner(usize),
+    NullifierMismatch,
+    InputAccountRoot,
+    InputNoteRoot(usize),
+    AccountIndexOrder,
+    CurrentIndexOrder,
+    InputNoteIndex(usize),
+    InvalidMemo,
+    InvalidSignature,
+    BalanceMismatch,
+    NegativeEnergy,
+}
+
+impl Display for TransferError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TransferError::InputNotesNotUnique => write!(f, "input notes are not unique"),
+            TransferError::OutputNotesNotUnique => write!(f, "nonzero output notes are not unique"),
+            TransferError::OutCommitMismatch => write!(f, "out_commit does not match output account and notes"),
+            TransferError::InputAccountOwner => write!(f, "input account is not owned by the signer"),
+            TransferError::OutputAccountOwner => write!(f, "output account is not owned by the signer"),
+            TransferError::InputNoteOwner(i) => write!(f, "input note {} is not owned by the signer", i),
+            TransferError::NullifierMismatch => write!(f, "nullifier does not match input account"),
+            TransferError::InputAccountRoot => write!(f, "input account is not in the tree with given root"),
+            TransferError::InputNoteRoot(i) => write!(f, "input note {} is not in the tree with given root", i),
+            TransferError::AccountIndexOrder => write!(f, "output account index is less than input account index"),
+            TransferError::CurrentIndexOrder => write!(f, "output account index is greater than current index"),
+            TransferError::InputNoteIndex(i) => write!(f, "input note {} is out of the account interval", i),
+            TransferError::InvalidMemo => write!(f, "memo should not be equal to -1"),
+            TransferError::InvalidSignature => write!(f, "invalid signature"),
+            TransferError::BalanceMismatch => write!(f, "inputs and outputs are not balanced"),
+            TransferError::NegativeEnergy => write!(f, "energy balance is negative"),
+        }
+    }
+}
+
+impl std::error::Error for TransferError {}
+
+
+fn proof_index<Fr:PrimeField, const L: usize>(proof:&MerkleProof<Fr, L>) -> Num<Fr> {
+    proof.path.iter().rev().fold(Num::ZERO, |acc, &b| acc.double() + if b { Num::ONE } else { Num::ZERO })
+}
+
+// native version of circuit::tx::c_transfer
+pub fn check_transfer<P:PoolParams, const IN: usize, const OUT: usize, const H: usize>(
+    p: &TransferPub<P::Fr>,
+    s: &TransferSec<P::Fr, IN, OUT, H>,
+    params: &P,
+) -> Result<(), TransferError> {
+    let (delta_value, delta_energy, current_index, poolid) = parse_delta(p.delta);
+    let (in_account, in_notes) = &s.tx.input;
+    let (out_account, out_notes) = &s.tx.output;
+
+    let input_index = in_account.i.to_num().to_uint();
+    let output_index = out_account.i.to_num().to_uint();
+
+    //input notes are unique
+    let in_account_hash = in_account.hash(params);
+    let in_note_hash = in_notes.iter().map(|n| n.hash(params)).collect::<Vec<_>>();
+    for i in 0..IN {
+        if in_note_hash[i+1..].contains(&in_note_hash[i]) {
+            return Err(TransferError::InputNotesNotUnique);
+        }
+    }
+
+    //output notes are unique or zero
+    let zero_note_hash = Note::zero().hash(params);
+    let out_note_hash = out_notes.iter().map(|n| n.hash(params)).collect::<Vec<_>>();
+    for i in 0..OUT {
+        if out_note_hash[i] != zero_note_hash && out_note_hash[i+1..].contains(&out_note_hash[i]) {
+            return Err(TransferError::OutputNotesNotUnique);
+        }
+    }
+
+    let out_hash = std::iter::once(out_account.hash(params)).chain(out_note_hash).collect::<Vec<_>>();
+    let out_commit = out_commitment_hash::<_, OUT>(&out_hash, params);
+    if out_commit != p.out_commit {
+        return Err(TransferError::OutCommitMismatch);
+    }
+
+    //check ownership
+    let eta = derive_key_eta(s.eddsa_a, params);
+    let owned = |d:Num<P::Fr>, p_d:Num<P::Fr>| derive_key_p_d(d, eta, params).x == p_d;
+
+    if !owned(in_account.d.to_num(), in_account.p_d) {
+        return Err(TransferError::InputAccountOwner);
+    }
+    if !owned(out_account.d.to_num(), out_account.p_d) {
+        return Err(TransferError::OutputAccountOwner);
+    }
+    if let Some(i) = in_notes.iter().position(|n| !owned(n.d.to_num(), n.p_d)) {
+        return Err(TransferError::InputNoteOwner(i));
+    }
+
+    //check nullifier and input account
+    let input_pos_index = proof_index(&s.in_proof.0);
+    if nullifier(in_account_hash, eta, input_pos_index, params) != p.nullifier {
+        return Err(TransferError::NullifierMismatch);
+    }
+
+    //all uninitialized empty accounts considered to be in the privacy set
+    let in_account_initial = (in_account.i.to_num() + in_account.b.to_num() + in_account.e.to_num()).is_zero() &&
+        in_account.d.to_num() == poolid;
+    if poseidon_merkle_proof_root(in_account_hash, &s.in_proof.0, params.compress()) != p.root && !in_account_initial {
+        return Err(TransferError::InputAccountRoot);
+    }
+
+    if input_index > output_index {
+        return Err(TransferError::AccountIndexOrder);
+    }
+
+    if output_index > current_index.to_uint() {
+        return Err(TransferError::CurrentIndexOrder);
+    }
+
+    let mut total_energy = delta_energy + in_account.b.to_num() * (current_index - input_pos_index);
+
+    //check input notes
+    for i in 0..IN {
+        let note_value = in_notes[i].b.to_num();
+        let note_index = proof_index(&s.in_proof.1[i]);
 
-    Num::from_uint(s).unwrap()
+        if !note_value.is_zero() {
+            if poseidon_merkle_proof_root(in_note_hash[i], &s.in_proof.1[i], params.compress()) != p.root {
+                return Err(TransferError::InputNoteRoot(i));
+            }
+
+            if !(input_index <= note_index.to_uint() && note_index.to_uint() < output_index) {
+                return Err(TransferError::InputNoteIndex(i));
+            }
+        }
+
+        total_energy += note_value * (current_index - note_index);
+    }
+
+    if (p.memo + Num::ONE).is_zero() {
+        return Err(TransferError::InvalidMemo);
+    }
+
+    //check signature
+    let in_hash = std::iter::once(in_account_hash).chain(in_note_hash).collect::<Vec<_>>();
+    let tx_hash = tx_hash(&in_hash, out_commit, params);
+    let signature_valid = match s.eddsa_s.to_other() {
+        Some(eddsa_s) => tx_verify(eddsa_s, s.eddsa_r, s.eddsa_a, tx_hash, params),
+        None => false
+    };
+    if !signature_valid {
+        return Err(TransferError::InvalidSignature);
+    }
+
+    //check balances
+    let total_value = delta_value + in_account.b.to_num() - out_account.b.to_num()
+        + in_notes.iter().fold(Num::ZERO, |acc, n| acc + n.b.to_num())
+        - out_notes.iter().fold(Num::ZERO, |acc, n| acc + n.b.to_num());
+    if !total_value.is_zero() {
+        return Err(TransferError::BalanceMismatch);
+    }
+
+    //final check energy
+    total_energy += in_account.e.to_num() - out_account.e.to_num();
+    if total_energy.to_uint() >> (Num::<P::Fr>::MODULUS_BITS - 2) != NumRepr::ZERO {
+        return Err(TransferError::NegativeEnergy);
+    }
+
+    Ok(())
+}
+
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TxBuilderError {
+    TooManyInputs,
+    TooManyOutputs,
+    InvalidBalance,
+    InvalidEnergy,
+    InvalidOutputIndex,
+    MemoConflict,
+    UnknownMemoVersion,
+    UnsupportedShape,
+}
+
+impl Display for TxBuilderError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let msg = match self {
+            TxBuilderError::TooManyInputs => "too many input notes",
+            TxBuilderError::TooManyOutputs => "too many output notes",
+            TxBuilderError::InvalidBalance => "output account balance is negative or out of range",
+            TxBuilderError::InvalidEnergy => "output account energy is negative or out of range",
+            TxBuilderError::InvalidOutputIndex => "output account index is out of [input account index, current index]",
+            TxBuilderError::MemoConflict => "memo hash is set explicitly, but build_with_memo computes it",
+            TxBuilderError::UnknownMemoVersion => "memo version is unknown",
+            TxBuilderError::UnsupportedShape => "memo is only supported for the default number of output notes",
+        };
+        write!(f, "{}", msg)
+    }
+}
+
+impl std::error::Error for TxBuilderError {}
+
+
+pub type TransferWithMemo<Fr, const IN: usize = { constants::IN }, const OUT: usize = { constants::OUT }, const H: usize = { HEIGHT }> = (TransferPub<Fr>, TransferSec<Fr, IN, OUT, H>, Vec<u8>);
+
+// Builds TransferPub and TransferSec for c_transfer. The output account gets the same address
+// as the input account, interval starting from the current index, and all remaining value and energy.
+#[derive(Clone)]
+pub struct TxBuilder<P:PoolParams, const IN: usize = { constants::IN }, const OUT: usize = { constants::OUT }, const H: usize = { HEIGHT }> {
+    sigma: Num<P::Fs>,
+    account: Account<P::Fr>,
+    account_proof: MerkleProof<P::Fr, H>,
+    notes: Vec<Note<P::Fr>>,
+    note_proofs: Vec<MerkleProof<P::Fr, H>>,
+    outputs: Vec<Note<P::Fr>>,
+    delta: Num<P::Fr>,
+    memo: Option<Num<P::Fr>>,
+    memo_header: Option<MemoHeader>,
+    root: Option<Num<P::Fr>>,
+    output_index: Option<Num<P::Fr>>,
+}
+
+impl<Fr:PrimeField, P:PoolParams<Fr=Fr>> TxBuilder<P> {
+    pub fn new(sigma:Num<P::Fs>, account:Account<Fr>, account_proof:MerkleProof<Fr, { HEIGHT }>, delta:Num<Fr>) -> Self {
+        Self::new_with_shape(sigma, account, account_proof, delta)
+    }
+}
+
+impl<Fr:PrimeField, P:PoolParams<Fr=Fr>, const IN: usize, const OUT: usize, const H: usize> TxBuilder<P, IN, OUT, H> {
+    // builder for the circuit with IN input and OUT output notes over the tree of height H,
+    // new uses the default shape and height
+    pub fn new_with_shape(sigma:Num<P::Fs>, account:Account<Fr>, account_proof:MerkleProof<Fr, H>, delta:Num<Fr>) -> Self {
+        Self {
+            sigma,
+            account,
+            account_proof,
+            notes: vec![],
+            note_proofs: vec![],
+            outputs: vec![],
+            delta,
+            memo: None,
+            memo_header: None,
+            root: None,
+            output_index: None,
+        }
+    }
+
+    pub fn input_note(mut self, note:Note<Fr>, proof:MerkleProof<Fr, H>) -> Self {
+        self.notes.push(note);
+        self.note_proofs.push(proof);
+        self
+    }
+
+    pub fn output(mut self, address:Address<Fr>, b:BoundedNum<Fr, { BALANCE_SIZE_BITS }>) -> Self {
+        self.outputs.push(Note {d: address.d, p_d: address.p_d, b, t: BoundedNum::new(Num::ZERO)});
+        self
+    }
+
+    // memo_hash of the memo encrypted outside, build_with_memo computes it itself
+    pub fn memo(mut self, memo:Num<Fr>) -> Self {
+        self.memo = Some(memo);
+        self
+    }
+
+    // version, fee, extra data, detection tags and cipher of the memo encrypted by build_with_memo,
+    // by default it is legacy memo without header
+    pub fn memo_header(mut self, header:MemoHeader) -> Self {
+        self.memo_header = Some(header);
+        self
+    }
+
+    // by default the root is computed from the input account proof
+    pub fn root(mut self, root:Num<Fr>) -> Self {
+        self.root = Some(root);
+        self
+    }
+
+    // by default the output account index is the current index, so all older notes can't be spent anymore.
+    // Lower index keeps unspent notes starting from it.
+    pub fn output_index(mut self, index:Num<Fr>) -> Self {
+        self.output_index = Some(index);
+        self
+    }
+
+    pub fn build<R:Rng>(&self, rng:&mut R, params:&P) -> Result<(TransferPub<Fr>, TransferSec<Fr, IN, OUT, H>), TxBuilderError> {
+        if self.notes.len() > IN {
+            return Err(TxBuilderError::TooManyInputs);
+        }
+        if self.outputs.len() > OUT {
+            return Err(TxBuilderError::TooManyOutputs);
+        }
+
+        let a = derive_key_a(self.sigma, params);
+        let eta = derive_key_eta(a.x, params);
+        let (delta_value, delta_energy, current_index, _) = parse_delta(self.delta);
+        let output_index = self.output_index.unwrap_or(current_index);
+        if output_index.to_uint() < self.account.i.to_num().to_uint() || output_index.to_uint() > current_index.to_uint() {
+            return Err(TxBuilderError::InvalidOutputIndex);
+        }
+
+        //dummy notes should be owned and unique too
+        let in_notes = self.notes.iter().cloned().zip(self.note_proofs.iter().cloned()).chain((self.notes.len()..IN).map(|_| {
+            let d:BoundedNum<_, { DIVERSIFIER_SIZE_BITS }> = rng.gen();
+            let note = Note {
+                d,
+                p_d: derive_key_p_d(d.to_num(), eta, params).x,
+                b: BoundedNum::new(Num::ZERO),
+                t: rng.gen(),
+            };
+            (note, self.account_proof.clone())
+        })).collect::<Vec<_>>();
+
+        let input_pos_index = proof_index(&self.account_proof);
+        let mut value = self.account.b.to_num() + delta_value;
+        for (note, _) in in_notes.iter() {
+            value += note.b.to_num();
+        }
+
+        // dummy notes have zero value and don't accrue energy
+        let leaf_index = |index:Num<Fr>| u64::try_from(index).map_err(|_| TxBuilderError::InvalidEnergy);
+        let notes = self.notes.iter().zip(self.note_proofs.iter())
+            .map(|(&note, proof)| Ok((leaf_index(proof_index(proof))?, note)))
+            .collect::<Result<Vec<_>, _>>()?;
+        let energy = total_energy(&self.account, leaf_index(input_pos_index)?, &notes, leaf_index(current_index)?)
+            .map_err(|_| TxBuilderError::InvalidEnergy)?;
+        let energy = Num::from(energy) + delta_energy;
+
+        let out_notes = self.outputs.iter().map(|&note| {
+            value -= note.b.to_num();
+            Note {t: rng.gen(), ..note}
+        }).chain((self.outputs.len()..OUT).map(|_| Note::zero())).collect::<Vec<_>>();
+
+        if value.to_uint() >> BALANCE_SIZE_BITS as u32 != NumRepr::ZERO {
+            return Err(TxBuilderError::InvalidBalance);
+        }
+        if energy.to_uint() >> ENERGY_SIZE_BITS as u32 != NumRepr::ZERO {
+            return Err(TxBuilderError::InvalidEnergy);
+        }
+
+        let out_account = Account {
+            d: self.account.d,
+            p_d: self.account.p_d,
+            i: BoundedNum::new(output_index),
+            b: BoundedNum::new(value),
+            e: BoundedNum::new(energy),
+        };
+
+        let in_account_hash = self.account.hash(params);
+        let in_hashes = std::iter::once(in_account_hash).chain(in_notes.iter().map(|(n, _)| n.hash(params))).collect::<Vec<_>>();
+        let out_hashes = std::iter::once(out_account.hash(params)).chain(out_notes.iter().map(|n| n.hash(params))).collect::<Vec<_>>();
+        let out_commit = out_commitment_hash::<_, OUT>(&out_hashes, params);
+        let tx_hash = tx_hash(&in_hashes, out_commit, params);
+        let (eddsa_s, eddsa_r) = tx_sign(self.sigma, tx_hash, params);
+
+        let root = self.root.unwrap_or_else(||
+            poseidon_merkle_proof_root(in_account_hash, &self.account_proof, params.compress())
+        );
+
+        let p = TransferPub {
+            root,
+            nullifier: nullifier(in_account_hash, eta, input_pos_index, params),
+            out_commit,
+            delta: self.delta,
+            memo: self.memo.unwrap_or(Num::ZERO),
+        };
+
+        let s = TransferSec {
+            tx: Tx {
+                input: (self.account, in_notes.iter().map(|(n, _)| *n).collect()),
+                output: (out_account, out_notes.into_iter().collect()),
+            },
+            in_proof: (self.account_proof.clone(), in_notes.into_iter().map(|(_, proof)| proof).collect()),
+            eddsa_s: eddsa_s.to_other().unwrap(),
+            eddsa_r,
+            eddsa_a: a.x,
+        };
+
+        Ok((p, s))
+    }
+
+    // encrypts the output account and the nonzero output notes with eta or ovk of the sender,
+    // as the memo header version says, and binds the memo to the transaction
+    pub fn build_with_memo<R:Rng>(&self, rng:&mut R, params:&P) -> Result<TransferWithMemo<Fr, IN, OUT, H>, TxBuilderError> {
+        if OUT != constants::OUT {
+            return Err(TxBuilderError::UnsupportedShape);
+        }
+        if self.memo.is_some() {
+            return Err(TxBuilderError::MemoConflict);
+        }
+        let a = derive_key_a(self.sigma, params).x;
+        let out_key = match self.memo_header.as_ref().map(|h| h.version) {
+            None | Some(constants::MEMO_VERSION_LEGACY) => derive_key_eta(a, params),
+            Some(constants::MEMO_VERSION_OVK) => derive_key_ovk(a, params),
+            _ => return Err(TxBuilderError::UnknownMemoVersion)
+        };
+
+        let (mut p, s) = self.build(rng, params)?;
+        let entropy: [u8; 32] = rng.gen();
+        let (account, notes) = (s.tx.output.0, &s.tx.output.1.as_slice()[..self.outputs.len()]);
+        let memo = match self.memo_header.as_ref() {
+            Some(header) => cipher::encrypt_with_header(&entropy, header, out_key, account, notes, params),
+            None => cipher::encrypt(&entropy, out_key, account, notes, params)
+        };
+        p.memo = memo_hash(&memo);
+        Ok((p, s, memo))
+    }
 }
@@ -0,0 +1,211 @@
+use crate::{constants::{self, HEIGHT, OUT, OUTPLUSONELOG}, fawkes_crypto::{
+        native::poseidon::MerkleProof,
+        ff_uint::Num,
+    }, native::{
+        params::PoolParams,
+        account::Account,
+        note::Note,
+        boundednum::BoundedNum,
+        key::{derive_key_p_d, ViewingKey},
+        cipher::{self, MemoView},
+        scanner::MemoScanner,
+        tree::MerkleTree,
+        storage::MemoryStorage,
+        planner::{plan_inputs_with_shape, PlanError, PlannedTx},
+        tx::TxBuilder,
+        energy::{total_energy, EnergyError},
+    }};
+
+use std::fmt::{self, Display};
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::io;
+
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WalletError {
+    IndexMismatch,
+    CommitmentMismatch,
+    Storage(io::ErrorKind),
+}
+
+impl Display for WalletError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            WalletError::IndexMismatch => write!(f, "transaction index does not match the next index of the wallet"),
+            WalletError::CommitmentMismatch => write!(f, "memo with our data does not match out_commit"),
+            WalletError::Storage(kind) => write!(f, "tree storage error: {:?}", kind)
+        }
+    }
+}
+
+impl std::error::Error for WalletError {}
+
+impl From<io::Error> for WalletError {
+    fn from(e: io::Error) -> Self {
+        WalletError::Storage(e.kind())
+    }
+}
+
+
+// State of one account, built from all transactions of the pool in order. Transactions with our data
+// are appended leaf by leaf, so merkle proofs for our account and notes are available, others only by out_commit.
+// IN is the number of input notes of the planned transactions, OUT is always constants::OUT.
+// H and L are the heights of the tree and of its tree of commitments, as in MerkleTree.
+pub struct Wallet<P:PoolParams, const IN: usize = { constants::IN }, const H: usize = { HEIGHT }, const L: usize = { HEIGHT - OUTPLUSONELOG }> {
+    vk: ViewingKey<P::Fr>,
+    scanner: MemoScanner<P::Fr>,
+    pool_id: u32,
+    tree: MerkleTree<P, MemoryStorage<P::Fr>, H, L>,
+    // latest account and its leaf index
+    account: Option<(u64, Account<P::Fr>)>,
+    // incoming notes which can be spent by the latest account
+    notes: BTreeMap<u64, Note<P::Fr>>,
+}
+
+impl<P:PoolParams, const IN: usize, const H: usize, const L: usize> Wallet<P, IN, H, L> {
+    pub fn new(vk:ViewingKey<P::Fr>, pool_id:u32, params:&P) -> Self {
+        Self {
+            scanner: MemoScanner::from_viewing_key(&vk, params),
+            vk,
+            pool_id,
+            tree: MerkleTree::new_with_height(MemoryStorage::new(), params),
+            account: None,
+            notes: BTreeMap::new(),
+        }
+    }
+
+    pub fn viewing_key(&self) -> &ViewingKey<P::Fr> {
+        &self.vk
+    }
+
+    pub fn pool_id(&self) -> u32 {
+        self.pool_id
+    }
+
+    pub fn tree(&self) -> &MerkleTree<P, MemoryStorage<P::Fr>, H, L> {
+        &self.tree
+    }
+
+    // leaf index of the next transaction
+    pub fn next_index(&self) -> u64 {
+        self.tree.next_index()
+    }
+
+    pub fn account(&self) -> Option<(u64, Account<P::Fr>)> {
+        self.account
+    }
+
+    // latest account or the initial account of the pool, with the merkle proof for TxBuilder
+    pub fn input_account(&self, params:&P) -> (Account<P::Fr>, MerkleProof<P::Fr, H>) {
+        match self.account {
+            Some((index, account)) => (account, self.tree.merkle_proof(index)),
+            None => {
+                let d = BoundedNum::new(Num::from(self.pool_id as u64));
+                let account = Account {
+                    d,
+                    p_d: derive_key_p_d(d.to_num(), self.vk.eta(), params).x,
+                    i: BoundedNum::new(Num::ZERO),
+                    b: BoundedNum::new(Num::ZERO),
+                    e: BoundedNum::new(Num::ZERO),
+                };
+                (account, self.tree.merkle_proof(0))
+            }
+        }
+    }
+
+    // notes with index in [account.i, next_index), as c_transfer accepts them
+    pub fn unspent_notes(&self) -> Vec<(u64, Note<P::Fr>)> {
+        self.notes.iter().map(|(&i, &n)| (i, n)).collect()
+    }
+
+    pub fn note_proof(&self, index:u64) -> MerkleProof<P::Fr, H> {
+        self.tree.merkle_proof(index)
+    }
+
+    pub fn balance(&self) -> Num<P::Fr> {
+        self.account.map(|(_, a)| a.b.to_num()).unwrap_or(Num::ZERO) +
+            self.notes.values().fold(Num::ZERO, |acc, n| acc + n.b.to_num())
+    }
+
+    // energy of the account and all unspent notes, if they are spent at next_index
+    pub fn energy(&self) -> Result<u128, EnergyError> {
+        let (index, account) = self.account.unwrap_or((0, Account {
+            d: BoundedNum::new(Num::ZERO),
+            p_d: Num::ZERO,
+            i: BoundedNum::new(Num::ZERO),
+            b: BoundedNum::new(Num::ZERO),
+            e: BoundedNum::new(Num::ZERO),
+        }));
+        total_energy(&account, index, &self.unspent_notes(), self.next_index())
+    }
+
+    pub fn plan(&self, target:u64) -> Result<Vec<PlannedTx>, PlanError> {
+        let balance = self.account.map(|(_, a)| u64::try_from(a.b.to_num()).unwrap()).unwrap_or(0);
+        plan_inputs_with_shape::<_, IN>(balance, &self.unspent_notes(), target)
+    }
+
+    // builder with input account, notes and root of the planned transaction, sigma should match the viewing key
+    pub fn tx_builder(&self, sigma:Num<P::Fs>, tx:&PlannedTx, delta:Num<P::Fr>, params:&P) -> TxBuilder<P, IN, OUT, H> {
+        let (account, account_proof) = self.input_account(params);
+        let builder = tx.notes.iter().fold(
+            TxBuilder::new_with_shape(sigma, account, account_proof, delta).root(self.tree.root()),
+            |b, &i| b.input_note(self.notes[&i], self.note_proof(i))
+        );
+        match tx.output_index {
+            Some(i) => builder.output_index(Num::from(i)),
+            None => builder
+        }
+    }
+
+    // Process the next transaction of the pool, index is the leaf index of its account.
+    // Returns true if the transaction contains our account or notes. Memo with our data which does not
+    // match out_commit is an error, the transaction is still appended by out_commit, so the wallet
+    // stays in sync with the pool.
+    pub fn ingest(&mut self, index:u64, out_commit:Num<P::Fr>, memo:&[u8], params:&P) -> Result<bool, WalletError> {
+        if index != self.next_index() {
+            return Err(WalletError::IndexMismatch);
+        }
+
+        let out = self.scanner.decrypt_out(memo, params).ok();
+        let in_notes = if self.scanner.quick_check(memo, params) {
+            self.scanner.decrypt_in(memo, params).unwrap_or_default()
+        } else {
+            vec![]
+        };
+
+        if out.is_none() && in_notes.iter().all(|n| n.is_none()) {
+            self.tree.append_commitment(out_commit, params)?;
+            return Ok(false);
+        }
+
+        let view = match MemoView::<P::Fr>::parse(memo) {
+            Ok(view) if cipher::verify_memo_commitment(memo, out_commit, params) => view,
+            _ => {
+                self.tree.append_commitment(out_commit, params)?;
+                return Err(WalletError::CommitmentMismatch);
+            }
+        };
+
+        let zero_note_hash = Note::zero().hash(params);
+        let mut leaves = view.item_hashes();
+        leaves.resize(OUT + 1, zero_note_hash);
+        for leaf in leaves {
+            self.tree.append(leaf, params)?;
+        }
+
+        if let Some((account, _)) = out {
+            let account_index = u64::try_from(account.i.to_num()).unwrap();
+            self.notes.retain(|&i, _| i >= account_index);
+            self.account = Some((index, account));
+        }
+
+        for (i, note) in in_notes.into_iter().enumerate() {
+            if let Some(note) = note {
+                self.notes.insert(index + 1 + i as u64, note);
+            }
+        }
+
+        Ok(true)
+    }
+}
@@ -2,6 +2,7 @@ mod evm_verifier;
 
 use libzeropool::{
     POOL_PARAMS,
+    constants,
     circuit::tree::{tree_update, CTreePub, CTreeSec},
     circuit::tx::{c_transfer, CTransferPub, CTransferSec},
     clap::Clap,
@@ -15,8 +16,9 @@ use fawkes_crypto::ff_uint::Num;
 use fawkes_crypto::backend::bellman_groth16::{verifier::{VK, verify}, prover::{Proof, prove}, setup::setup, Parameters};
 use evm_verifier::generate_sol_data;
 use fawkes_crypto::circuit::cs::CS;
-use fawkes_crypto::rand::rngs::OsRng;
-use libzeropool::helpers::sample_data::State;
+use fawkes_crypto::rand::{Rng, rngs::OsRng};
+use libzeropool::helpers::sample_data::{State, N_ITEMS};
+use libzeropool::native::tree::{MerkleTree, make_tree_update};
 use convert_case::{Case, Casing};
 
 #[derive(Clap)]
@@ -42,7 +44,7 @@ enum SubCommand {
 /// A subcommand for generating a SNARK proof
 #[derive(Clap)]
 struct ProveOpts {
-    /// Circuit for prooving (transfer|tree_update)
+    /// Circuit for prooving (transfer|transfer_inXout127|tree_update)
     #[clap(short = "c", long = "circuit", default_value = "transfer")]
     circuit: String,
     /// Snark trusted setup parameters file
@@ -62,7 +64,7 @@ struct ProveOpts {
 /// A subcommand for verifying a SNARK proof
 #[derive(Clap)]
 struct VerifyOpts {
-    /// Circuit for verifying (transfer|tree_update)
+    /// Circuit for verifying (transfer|transfer_inXout127|tree_update)
     #[clap(short = "c", long = "circuit", default_value = "transfer")]
     circuit: String,
     /// Snark verification key
@@ -79,7 +81,7 @@ struct VerifyOpts {
 /// A subcommand for generating a trusted setup parameters
 #[derive(Clap)]
 struct SetupOpts {
-    /// Circuit for parameter generation (transfer|tree_update)
+    /// Circuit for parameter generation (transfer|transfer_inXout127|tree_update)
     #[clap(short = "c", long = "circuit", default_value = "transfer")]
     circuit: String,
     /// Snark trusted setup parameters file
@@ -93,7 +95,7 @@ struct SetupOpts {
 /// A subcommand for generating a Solidity verifier smart contract
 #[derive(Clap)]
 struct GenerateVerifierOpts {
-    /// Circuit for verifying (transfer|tree_update)
+    /// Circuit for verifying (transfer|transfer_inXout127|tree_update)
     #[clap(short = "c", long = "circuit", default_value = "transfer")]
     circuit: String,
     /// Snark verification key
@@ -109,7 +111,7 @@ struct GenerateVerifierOpts {
 
 #[derive(Clap)]
 struct GenerateTestDataOpts {
-    /// Circuit for testing (transfer|tree_update)
+    /// Circuit for testing (transfer|transfer_inXout127|tree_update)
     #[clap(short = "c", long = "circuit", default_value = "transfer")]
     circuit: String,
     /// Input object JSON file
@@ -121,18 +123,49 @@ fn tree_circuit<C:CS<Fr=Fr>>(public: CTreePub<C>, secret: CTreeSec<C>) {
     tree_update(&public, &secret, &*POOL_PARAMS);
 }
 
-fn tx_circuit<C:CS<Fr=Fr>>(public: CTransferPub<C>, secret: CTransferSec<C>) {
+fn tx_circuit<C:CS<Fr=Fr>, const IN: usize, const OUT: usize>(public: CTransferPub<C>, secret: CTransferSec<C, IN, OUT>) {
     c_transfer(&public, &secret, &*POOL_PARAMS);
 }
 
+// transfer is the default shape, other shapes are named transfer_inXoutY
+fn transfer_shape(circuit:&str) -> Option<(usize, usize)> {
+    match circuit {
+        "transfer" => Some((constants::IN, constants::OUT)),
+        "transfer_in1out127" => Some((1, 127)),
+        "transfer_in3out127" => Some((3, 127)),
+        "transfer_in7out127" => Some((7, 127)),
+        "transfer_in15out127" => Some((15, 127)),
+        _ => None
+    }
+}
+
+fn tx_setup<const IN: usize, const OUT: usize>() -> Parameters<Bn256> {
+    setup::<Bn256, _, _, _>(tx_circuit::<_, IN, OUT>)
+}
+
+fn tx_prove<const IN: usize, const OUT: usize>(params:&Parameters<Bn256>, object_str:&str) -> (Vec<Num<Fr>>, Proof<Bn256>) {
+    let (public, secret) = serde_json::from_str(object_str).unwrap();
+    prove(params, &public, &secret, tx_circuit::<_, IN, OUT>)
+}
+
+fn tx_test_data<const IN: usize, const OUT: usize>() -> String {
+    let mut rng = OsRng::default();
+    let state = State::random_sample_state(&mut rng, &*POOL_PARAMS);
+    let data = state.random_sample_transfer_with_shape::<_, IN, OUT>(&mut rng, &*POOL_PARAMS);
+    serde_json::to_string_pretty(&data).unwrap()
+}
+
 fn cli_setup(o:SetupOpts) {
     let params_path = o.params.unwrap_or(format!("{}_params.bin", o.circuit));
     let vk_path = o.vk.unwrap_or(format!("{}_verification_key.json", o.circuit));
     
 
-    let params = match o.circuit.as_str() {
-        "tree_update" => setup::<Bn256, _, _, _>(tree_circuit),
-        "transfer" => setup::<Bn256, _, _, _>(tx_circuit),
+    let params = match (o.circuit.as_str(), transfer_shape(&o.circuit)) {
+        ("tree_update", _) => setup::<Bn256, _, _, _>(tree_circuit),
+        (_, Some((1, 127))) => tx_setup::<1, 127>(),
+        (_, Some((3, 127))) => tx_setup::<3, 127>(),
+        (_, Some((7, 127))) => tx_setup::<7, 127>(),
+        (_, Some((15, 127))) => tx_setup::<15, 127>(),
         _ => panic!("Wrong cicruit parameter")
     };
 
@@ -178,15 +211,27 @@ fn cli_verify(o:VerifyOpts) {
 fn cli_generate_test_data(o:GenerateTestDataOpts) {
     let object_path = o.object.unwrap_or(format!("{}_object.json", o.circuit));
 
-    match o.circuit.as_str() {
-        "transfer" => {
-            let mut rng = OsRng::default();
-            let state = State::random_sample_state(&mut rng, &*POOL_PARAMS);
-            let data = state.random_sample_transfer(&mut rng, &*POOL_PARAMS);
+    match (o.circuit.as_str(), transfer_shape(&o.circuit)) {
+        ("tree_update", _) => {
+            let mut rng = OsRng;
+            let mut tree = MerkleTree::new(&*POOL_PARAMS);
+            for _ in 0..rng.gen_range(0, N_ITEMS) {
+                tree.append_commitment(rng.gen(), &*POOL_PARAMS).unwrap();
+            }
+            let data = make_tree_update(&mut tree, rng.gen(), &*POOL_PARAMS).unwrap();
             let data_str = serde_json::to_string_pretty(&data).unwrap();
-            std::fs::write(object_path, &data_str.into_bytes()).unwrap();
+            std::fs::write(object_path, data_str.into_bytes()).unwrap();
+        },
+        (_, Some(shape)) => {
+            let data_str = match shape {
+                (1, 127) => tx_test_data::<1, 127>(),
+                (3, 127) => tx_test_data::<3, 127>(),
+                (7, 127) => tx_test_data::<7, 127>(),
+                (15, 127) => tx_test_data::<15, 127>(),
+                _ => panic!("Wrong cicruit parameter")
+            };
+            std::fs::write(object_path, data_str.into_bytes()).unwrap();
         },
-        "tree_update" => std::unimplemented!(),
         _ => panic!("Wrong cicruit parameter")
     }
 
@@ -206,12 +251,16 @@ fn cli_prove(o:ProveOpts) {
     let params = Parameters::<Bn256>::read(&mut params_data_cur, false, false).unwrap();
     let object_str = std::fs::read_to_string(object_path).unwrap();
 
-    let (inputs, snark_proof) = if o.circuit.eq("tree_update") {
-        let (public, secret) = serde_json::from_str(&object_str).unwrap();
-        prove(&params, &public, &secret, tree_circuit)
-    } else {
-        let (public, secret) = serde_json::from_str(&object_str).unwrap();
-        prove(&params, &public, &secret, tx_circuit)
+    let (inputs, snark_proof) = match (o.circuit.as_str(), transfer_shape(&o.circuit)) {
+        ("tree_update", _) => {
+            let (public, secret) = serde_json::from_str(&object_str).unwrap();
+            prove(&params, &public, &secret, tree_circuit)
+        },
+        (_, Some((1, 127))) => tx_prove::<1, 127>(&params, &object_str),
+        (_, Some((3, 127))) => tx_prove::<3, 127>(&params, &object_str),
+        (_, Some((7, 127))) => tx_prove::<7, 127>(&params, &object_str),
+        (_, Some((15, 127))) => tx_prove::<15, 127>(&params, &object_str),
+        _ => panic!("Wrong cicruit parameter")
     };
 
 
@@ -0,0 +1,48 @@
+use libzeropool::POOL_PARAMS;
+
+use libzeropool::fawkes_crypto::rand::{thread_rng, Rng};
+use libzeropool::fawkes_crypto::ff_uint::Num;
+use libzeropool::native::address::{Address, AddressError};
+
+use libzeropool::fawkes_crypto::engines::bn256::Fr;
+
+
+#[test]
+fn test_address_encoding() {
+    let mut rng = thread_rng();
+    let eta: Num<Fr> = rng.gen();
+    let address = Address::generate(eta, &mut rng, &*POOL_PARAMS);
+    assert!(address.is_own(eta, &*POOL_PARAMS));
+    assert!(!address.is_own(rng.gen(), &*POOL_PARAMS));
+
+    let s = address.encode(42).unwrap();
+    assert_eq!(Address::decode(&s, 42, &*POOL_PARAMS), Ok(address));
+    assert_eq!(Address::decode(&s, 43, &*POOL_PARAMS), Err(AddressError::PoolIdMismatch));
+    assert_eq!(Address::decode(&s[1..], 42, &*POOL_PARAMS), Err(AddressError::InvalidLength));
+    assert_eq!(Address::decode("0OIl", 42, &*POOL_PARAMS), Err(AddressError::InvalidBase58));
+    assert_eq!(address.encode(1 << 24), Err(AddressError::PoolIdRange));
+    assert_eq!(Address::decode(&s, 1 << 24, &*POOL_PARAMS), Err(AddressError::PoolIdRange));
+
+    let mut data = bs58::decode(&s).into_vec().unwrap();
+    data[5] ^= 1;
+    assert_eq!(Address::decode(&bs58::encode(&data).into_string(), 42, &*POOL_PARAMS), Err(AddressError::InvalidChecksum));
+
+    let other = Address::generate(eta, &mut rng, &*POOL_PARAMS);
+    assert!(other != address && other.is_own(eta, &*POOL_PARAMS));
+}
+
+#[test]
+fn test_address_invalid_public_key() {
+    let mut rng = thread_rng();
+    let mut address: Address<Fr> = Address::generate(rng.gen(), &mut rng, &*POOL_PARAMS);
+
+    // roughly half of the field elements are not x coordinates of any curve point
+    let s = loop {
+        address.p_d = rng.gen();
+        let s = address.encode(0).unwrap();
+        if Address::decode(&s, 0, &*POOL_PARAMS).is_err() {
+            break s;
+        }
+    };
+    assert_eq!(Address::decode(&s, 0, &*POOL_PARAMS), Err(AddressError::InvalidPublicKey));
+}
@@ -14,7 +14,7 @@ use std::time::Instant;
 fn test_circuit_tx() {
     let ref cs = DebugCS::rc_new();
     let ref p = CTransferPub::alloc(cs, None);
-    let ref s = CTransferSec::alloc(cs, None);
+    let ref s = CTransferSec::<_>::alloc(cs, None);
 
     
     let mut n_gates = cs.borrow().num_gates();
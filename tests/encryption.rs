@@ -1,11 +1,11 @@
-use libzeropool::POOL_PARAMS;
+use libzeropool::{POOL_PARAMS, constants};
 
 use libzeropool::fawkes_crypto::rand::{thread_rng, Rng};
 use libzeropool::native::{
     note::Note,
     account::Account,
-    key::{derive_key_p_d},
-    cipher
+    key::{derive_key_p_d, ViewingKey},
+    cipher::{self, MemoHeader, MemoError, MemoView}
 };
 
 use libzeropool::fawkes_crypto::engines::bn256::Fr;
@@ -42,4 +42,254 @@ fn test_encryption() {
     assert!(result_out.len()==2 && result_out[0].is_some() && result_out[0].unwrap()==note[0] && result_out[1].is_none(), "Wrong incoming data decrypted");
 
 
-}
\ No newline at end of file
+}
+
+
+#[test]
+fn test_encryption_with_ovk() {
+    let mut rng = thread_rng();
+    let sender = ViewingKey::from_sigma(rng.gen(), &*POOL_PARAMS);
+    let receiver = ViewingKey::from_sigma(rng.gen(), &*POOL_PARAMS);
+
+    let mut account: Account<Fr> = Account::sample(&mut rng, &*POOL_PARAMS);
+    let mut note:Vec<Note<Fr>> = (0..2).map(|_| Note::sample(&mut rng, &*POOL_PARAMS)).collect();
+    account.p_d = derive_key_p_d(account.d.to_num(), sender.eta(), &*POOL_PARAMS).x;
+    note[0].p_d = derive_key_p_d(note[0].d.to_num(), receiver.eta(), &*POOL_PARAMS).x;
+    note[1].p_d = derive_key_p_d(note[1].d.to_num(), sender.eta(), &*POOL_PARAMS).x;
+
+    let entropy = (0..32).map(|_| rng.gen()).collect::<Vec<_>>();
+    let ciphertext = cipher::encrypt_with_ovk(&entropy, sender.ovk(&*POOL_PARAMS), account, &note, &*POOL_PARAMS);
+
+    assert_eq!(sender.outgoing(&*POOL_PARAMS).decrypt_out(&ciphertext, &*POOL_PARAMS), Some((account, note.clone())));
+    assert_eq!(sender.decrypt_out(&ciphertext, &*POOL_PARAMS), Some((account, note.clone())));
+    assert_eq!(cipher::decrypt_out(sender.eta(), &ciphertext, &*POOL_PARAMS), None);
+
+    assert_eq!(receiver.incoming().decrypt_in(&ciphertext, &*POOL_PARAMS), vec![Some(note[0]), None]);
+    assert_eq!(sender.incoming().decrypt_in(&ciphertext, &*POOL_PARAMS), vec![None, Some(note[1])]);
+
+    let legacy = cipher::encrypt(&entropy, sender.eta(), account, &note, &*POOL_PARAMS);
+    assert_eq!(sender.decrypt_out(&legacy, &*POOL_PARAMS), Some((account, note.clone())));
+    assert_eq!(sender.outgoing(&*POOL_PARAMS).decrypt_out(&legacy, &*POOL_PARAMS), None);
+}
+
+#[test]
+fn test_versioned_memo() {
+    let mut rng = thread_rng();
+    let sender = ViewingKey::from_sigma(rng.gen(), &*POOL_PARAMS);
+    let receiver_eta = rng.gen();
+
+    let mut account: Account<Fr> = Account::sample(&mut rng, &*POOL_PARAMS);
+    let mut note:Vec<Note<Fr>> = (0..2).map(|_| Note::sample(&mut rng, &*POOL_PARAMS)).collect();
+    account.p_d = derive_key_p_d(account.d.to_num(), sender.eta(), &*POOL_PARAMS).x;
+    note[0].p_d = derive_key_p_d(note[0].d.to_num(), receiver_eta, &*POOL_PARAMS).x;
+    let entropy = (0..32).map(|_| rng.gen()).collect::<Vec<_>>();
+
+    let legacy = cipher::encrypt(&entropy, sender.eta(), account, &note, &*POOL_PARAMS);
+    assert!(legacy[0] != constants::MEMO_VERSION_PREFIX);
+    let (header, body) = cipher::parse_memo_header(&legacy).unwrap();
+    assert_eq!(header, MemoHeader::new(constants::MEMO_VERSION_LEGACY));
+    assert_eq!(body, &legacy[..]);
+
+    let header = MemoHeader {
+        version: constants::MEMO_VERSION_LEGACY,
+        fee: Some(100500),
+        extra_data: Some(vec![1, 2, 3]),
+        detection_tags: None,
+        hardened: false,
+    };
+    let memo = cipher::encrypt_with_header(&entropy, &header, sender.eta(), account, &note, &*POOL_PARAMS);
+    let (parsed, body) = cipher::parse_memo_header(&memo).unwrap();
+    assert_eq!(parsed, header);
+    assert_eq!(body, &legacy[..]);
+    assert_eq!(cipher::decrypt_out(sender.eta(), &memo, &*POOL_PARAMS), Some((account, note.clone())));
+    assert_eq!(cipher::decrypt_in(receiver_eta, &memo, &*POOL_PARAMS), vec![Some(note[0]), None]);
+
+    let header = MemoHeader {
+        version: constants::MEMO_VERSION_OVK,
+        fee: Some(7),
+        extra_data: None,
+        detection_tags: None,
+        hardened: false,
+    };
+    let memo = cipher::encrypt_with_header(&entropy, &header, sender.ovk(&*POOL_PARAMS), account, &note, &*POOL_PARAMS);
+    assert_eq!(cipher::parse_memo_header(&memo).unwrap().0, header);
+    assert_eq!(cipher::decrypt_out(sender.eta(), &memo, &*POOL_PARAMS), None);
+    assert_eq!(sender.decrypt_out(&memo, &*POOL_PARAMS), Some((account, note.clone())));
+    assert_eq!(cipher::decrypt_in(receiver_eta, &memo, &*POOL_PARAMS), vec![Some(note[0]), None]);
+
+    let mut unknown_version = memo.clone();
+    unknown_version[1] = constants::MEMO_VERSION_OVK + 1;
+    assert!(cipher::parse_memo_header(&unknown_version).is_none());
+    assert_eq!(cipher::decrypt_in(receiver_eta, &unknown_version, &*POOL_PARAMS), vec![]);
+
+    let mut unknown_flags = memo.clone();
+    unknown_flags[2] |= 0x80;
+    assert!(cipher::parse_memo_header(&unknown_flags).is_none());
+}
+
+#[test]
+fn test_memo_errors() {
+    let mut rng = thread_rng();
+    let sender_eta = rng.gen();
+    let receiver_eta = rng.gen();
+
+    let mut account: Account<Fr> = Account::sample(&mut rng, &*POOL_PARAMS);
+    let mut note:Vec<Note<Fr>> = (0..2).map(|_| Note::sample(&mut rng, &*POOL_PARAMS)).collect();
+    account.p_d = derive_key_p_d(account.d.to_num(), sender_eta, &*POOL_PARAMS).x;
+    note[0].p_d = derive_key_p_d(note[0].d.to_num(), receiver_eta, &*POOL_PARAMS).x;
+    let entropy = (0..32).map(|_| rng.gen()).collect::<Vec<_>>();
+    let memo = cipher::encrypt(&entropy, sender_eta, account, &note, &*POOL_PARAMS);
+
+    assert_eq!(cipher::try_decrypt_out(sender_eta, &memo, &*POOL_PARAMS), Ok((account, note.clone())));
+    assert_eq!(cipher::try_decrypt_in(receiver_eta, &memo, &*POOL_PARAMS), Ok(vec![Some(note[0]), None]));
+
+    assert_eq!(cipher::try_decrypt_out(rng.gen(), &memo, &*POOL_PARAMS), Err(MemoError::DecryptionFailed));
+    assert_eq!(cipher::try_decrypt_in(rng.gen(), &memo, &*POOL_PARAMS), Ok(vec![None, None]));
+    assert_eq!(cipher::try_decrypt_out(sender_eta, &memo[..memo.len() - 1], &*POOL_PARAMS), Err(MemoError::Truncated));
+    assert_eq!(cipher::try_decrypt_in(receiver_eta, &memo[..memo.len() - 1], &*POOL_PARAMS), Err(MemoError::Truncated));
+    assert_eq!(cipher::try_decrypt_out(sender_eta, &[], &*POOL_PARAMS), Err(MemoError::Truncated));
+    assert_eq!(cipher::try_decrypt_out(sender_eta, &[0, constants::MEMO_VERSION_OVK + 1, 0, 0], &*POOL_PARAMS), Err(MemoError::UnknownVersion));
+    assert_eq!(cipher::try_decrypt_out_with_ovk(sender_eta, &memo, &*POOL_PARAMS), Err(MemoError::VersionMismatch));
+
+    let mut bad_count = memo.clone();
+    bad_count[..4].copy_from_slice(&200u32.to_le_bytes());
+    assert_eq!(cipher::try_decrypt_out(sender_eta, &bad_count, &*POOL_PARAMS), Err(MemoError::InvalidItemsNum));
+
+    // account hash is the first field after items count
+    let mut bad_hash = memo.clone();
+    bad_hash[4] ^= 1;
+    assert_eq!(cipher::try_decrypt_out(sender_eta, &bad_hash, &*POOL_PARAMS), Err(MemoError::HashMismatch));
+
+    // first note hash
+    let mut bad_note_hash = memo.clone();
+    bad_note_hash[36] ^= 1;
+    assert_eq!(cipher::try_decrypt_in(receiver_eta, &bad_note_hash, &*POOL_PARAMS), Err(MemoError::HashMismatch));
+    assert_eq!(cipher::decrypt_in(receiver_eta, &bad_note_hash, &*POOL_PARAMS), vec![None, None]);
+
+    // a_p ephemeral key, roughly half of the field elements are not on the curve
+    let a_p_offset = 4 + 3 * 32;
+    let bad_point = loop {
+        let mut m = memo.clone();
+        m[a_p_offset..a_p_offset + 31].iter_mut().for_each(|b| *b = rng.gen());
+        if cipher::try_decrypt_out(sender_eta, &m, &*POOL_PARAMS) == Err(MemoError::InvalidPoint) {
+            break m;
+        }
+    };
+    assert_eq!(cipher::decrypt_out(sender_eta, &bad_point, &*POOL_PARAMS), None);
+}
+
+#[test]
+fn test_detection_tags() {
+    let mut rng = thread_rng();
+    let sender_eta = rng.gen();
+    let receiver_eta = rng.gen();
+
+    let mut account: Account<Fr> = Account::sample(&mut rng, &*POOL_PARAMS);
+    let mut note:Vec<Note<Fr>> = (0..3).map(|_| Note::sample(&mut rng, &*POOL_PARAMS)).collect();
+    account.p_d = derive_key_p_d(account.d.to_num(), sender_eta, &*POOL_PARAMS).x;
+    note[2].p_d = derive_key_p_d(note[2].d.to_num(), receiver_eta, &*POOL_PARAMS).x;
+    let entropy = (0..32).map(|_| rng.gen()).collect::<Vec<_>>();
+
+    let header = MemoHeader::new(constants::MEMO_VERSION_LEGACY).with_detection_tags();
+    let memo = cipher::encrypt_with_header(&entropy, &header, sender_eta, account, &note, &*POOL_PARAMS);
+    assert_eq!(cipher::parse_memo_header(&memo).unwrap().0.detection_tags.unwrap().len(), 3);
+
+    assert!(cipher::quick_check(receiver_eta, &memo, &*POOL_PARAMS));
+    assert!(!cipher::quick_check(sender_eta, &memo, &*POOL_PARAMS));
+    assert!(!cipher::quick_check(rng.gen(), &memo, &*POOL_PARAMS));
+    assert_eq!(cipher::decrypt_in(receiver_eta, &memo, &*POOL_PARAMS), vec![None, None, Some(note[2])]);
+    assert_eq!(cipher::decrypt_out(sender_eta, &memo, &*POOL_PARAMS), Some((account, note.clone())));
+
+    // memos without tags can't be rejected early
+    let legacy = cipher::encrypt(&entropy, sender_eta, account, &note, &*POOL_PARAMS);
+    assert!(cipher::quick_check(rng.gen(), &legacy, &*POOL_PARAMS));
+
+    let mut bad_tag = memo.clone();
+    // prefix, version, flags, tags count and the tag of the last note
+    bad_tag[1 + 1 + 2 + 4 + 2 * constants::DETECTION_TAG_SIZE] ^= 1;
+    assert!(!cipher::quick_check(receiver_eta, &bad_tag, &*POOL_PARAMS));
+}
+
+#[test]
+fn test_memo_view() {
+    let mut rng = thread_rng();
+    let sender_eta = rng.gen();
+
+    let account: Account<Fr> = Account::sample(&mut rng, &*POOL_PARAMS);
+    let note:Vec<Note<Fr>> = (0..3).map(|_| Note::sample(&mut rng, &*POOL_PARAMS)).collect();
+    let entropy = (0..32).map(|_| rng.gen()).collect::<Vec<_>>();
+
+    let mut header = MemoHeader::new(constants::MEMO_VERSION_LEGACY).with_detection_tags();
+    header.fee = Some(100);
+    let memo = cipher::encrypt_with_header(&entropy, &header, sender_eta, account, &note, &*POOL_PARAMS);
+
+    let view = MemoView::<Fr>::parse(&memo).unwrap();
+    assert_eq!(view.header().fee, Some(100));
+    assert_eq!(view.items_num(), 4);
+    assert_eq!(view.notes_num(), 3);
+    assert_eq!(view.account_hash(), account.hash(&*POOL_PARAMS));
+    assert!((0..3).all(|i| view.note_hash(i) == note[i].hash(&*POOL_PARAMS)));
+    assert_eq!(view.item_hashes(), std::iter::once(account.hash(&*POOL_PARAMS)).chain(note.iter().map(|n| n.hash(&*POOL_PARAMS))).collect::<Vec<_>>());
+    assert_eq!(view.shared_secret_ciphertext().len(), 4 * constants::U256_SIZE + constants::POLY_1305_TAG_SIZE);
+    assert_eq!(view.note_ciphertext(2).len(), constants::note_size_bits::<Fr>() / 8 + constants::POLY_1305_TAG_SIZE);
+
+    // trailing data is ignored, missing data is not
+    let mut longer = memo.clone();
+    longer.push(0);
+    assert!(MemoView::<Fr>::parse(&longer).is_ok());
+    assert_eq!(MemoView::<Fr>::parse(&memo[..memo.len() - 1]).unwrap_err(), MemoError::Truncated);
+
+    let legacy = cipher::encrypt(&entropy, sender_eta, account, &note, &*POOL_PARAMS);
+    let legacy_view = MemoView::<Fr>::parse(&legacy).unwrap();
+    assert_eq!(legacy_view.header(), &MemoHeader::new(constants::MEMO_VERSION_LEGACY));
+    assert_eq!(legacy_view.a_p(), view.a_p());
+    assert_eq!(legacy_view.note_a_pub(1), view.note_a_pub(1));
+
+    let mut bad_hash = legacy.clone();
+    // items count and the last byte of the account hash
+    bad_hash[4 + constants::U256_SIZE - 1] = 0xff;
+    assert_eq!(MemoView::<Fr>::parse(&bad_hash).unwrap_err(), MemoError::InvalidFieldElement);
+}
+
+#[test]
+fn test_hardened_cipher() {
+    let mut rng = thread_rng();
+    let sender = ViewingKey::from_sigma(rng.gen(), &*POOL_PARAMS);
+    let receiver_eta = rng.gen();
+
+    let account: Account<Fr> = Account::sample(&mut rng, &*POOL_PARAMS);
+    let mut note:Vec<Note<Fr>> = (0..2).map(|_| Note::sample(&mut rng, &*POOL_PARAMS)).collect();
+    note[1].p_d = derive_key_p_d(note[1].d.to_num(), receiver_eta, &*POOL_PARAMS).x;
+    let entropy = (0..32).map(|_| rng.gen()).collect::<Vec<_>>();
+
+    let header = MemoHeader::new(constants::MEMO_VERSION_OVK).with_detection_tags().with_hardened_cipher();
+    let memo = cipher::encrypt_with_header(&entropy, &header, sender.ovk(&*POOL_PARAMS), account, &note, &*POOL_PARAMS);
+    assert!(cipher::parse_memo_header(&memo).unwrap().0.hardened);
+    assert_eq!(memo.len(), cipher::encrypt_with_ovk(&entropy, sender.ovk(&*POOL_PARAMS), account, &note, &*POOL_PARAMS).len()
+        + 4 + 2 * constants::DETECTION_TAG_SIZE + 4 * constants::HARDENED_CIPHER_OVERHEAD);
+
+    assert_eq!(sender.decrypt_out(&memo, &*POOL_PARAMS), Some((account, note.clone())));
+    assert_eq!(cipher::decrypt_in(receiver_eta, &memo, &*POOL_PARAMS), vec![None, Some(note[1])]);
+    assert!(cipher::quick_check(receiver_eta, &memo, &*POOL_PARAMS));
+
+    let view = MemoView::<Fr>::parse(&memo).unwrap();
+    let offset = memo.len() - view.note_ciphertext(1).len();
+
+    // nonces differ even when the entropy is reused with the same keys
+    let mut other_note = note.clone();
+    other_note[1].b = rng.gen();
+    let other = cipher::encrypt_with_header(&entropy, &header, sender.ovk(&*POOL_PARAMS), account, &other_note, &*POOL_PARAMS);
+    assert_eq!(view.note_a_pub(1), MemoView::<Fr>::parse(&other).unwrap().note_a_pub(1));
+    assert_ne!(memo[offset..offset + constants::XCHACHA20_NONCE_SIZE], other[offset..offset + constants::XCHACHA20_NONCE_SIZE]);
+
+    let mut bad_commitment = memo.clone();
+    bad_commitment[offset + constants::XCHACHA20_NONCE_SIZE] ^= 1;
+    assert_eq!(cipher::try_decrypt_in(receiver_eta, &bad_commitment, &*POOL_PARAMS), Ok(vec![None, None]));
+
+    let mut bad_nonce = memo.clone();
+    bad_nonce[offset] ^= 1;
+    assert_eq!(cipher::try_decrypt_in(receiver_eta, &bad_nonce, &*POOL_PARAMS), Ok(vec![None, None]));
+
+    let legacy = cipher::encrypt_with_header(&entropy, &MemoHeader::new(constants::MEMO_VERSION_LEGACY).with_hardened_cipher(), sender.eta(), account, &note, &*POOL_PARAMS);
+    assert_eq!(cipher::decrypt_out(sender.eta(), &legacy, &*POOL_PARAMS), Some((account, note.clone())));
+}
@@ -0,0 +1,64 @@
+use libzeropool::POOL_PARAMS;
+
+use libzeropool::fawkes_crypto::rand::thread_rng;
+use libzeropool::fawkes_crypto::ff_uint::Num;
+use libzeropool::helpers::sample_data::State;
+use libzeropool::native::{
+    account::Account,
+    energy::{accrued_energy, energy_withdrawal_delta, total_energy, EnergyError},
+    boundednum::BoundedNum,
+    tx::{check_transfer, Delta, DeltaError, TxBuilder, TxBuilderError},
+};
+
+use std::convert::TryFrom;
+
+
+#[test]
+fn test_energy() {
+    assert_eq!(accrued_energy(10, 5, 8), Ok(30));
+    assert_eq!(accrued_energy(10, 8, 8), Ok(0));
+    assert_eq!(accrued_energy(10, 9, 8), Err(EnergyError::IndexOrder));
+    assert_eq!(accrued_energy(u64::MAX, 0, 1 << 48), Ok((u64::MAX as u128) << 48));
+
+    let mut rng = thread_rng();
+    let state = State::random_sample_state(&mut rng, &*POOL_PARAMS);
+    let (account, _) = state.items[state.account_id];
+    let notes = state.note_id.iter().map(|&i| ((i * 2 + 1) as u64, state.items[i].1)).collect::<Vec<_>>();
+    let current_index = state.items.len() as u64 * 2;
+    let account_index = state.account_id as u64 * 2;
+
+    let energy = total_energy(&account, account_index, &notes, current_index).unwrap();
+    let full_account = Account {e: BoundedNum::new(Num::from((1u128 << 112) - 1)), ..account};
+    assert_eq!(total_energy(&full_account, account_index, &notes, current_index), Err(EnergyError::EnergyOutOfRange));
+
+    let build = |delta| notes.iter().fold(
+        TxBuilder::new(state.sigma, account, state.merkle_proof(state.account_id * 2), delta),
+        |b, &(i, n)| b.input_note(n, state.merkle_proof(i as usize))
+    ).build(&mut thread_rng(), &*POOL_PARAMS).unwrap();
+
+    // energy of the output account matches c_transfer
+    let delta = energy_withdrawal_delta(energy, 0, 0, current_index, 0).unwrap();
+    let (p, s) = build(delta.into());
+    assert_eq!(check_transfer(&p, &s, &*POOL_PARAMS), Ok(()));
+    assert_eq!(u128::try_from(s.tx.output.0.e.to_num()).unwrap(), energy);
+
+    let amount = u64::try_from(account.b.to_num()).unwrap() / 2;
+    let delta = energy_withdrawal_delta(energy, energy / 3, amount, current_index, 0).unwrap();
+    assert_eq!((delta.value(), delta.energy()), (-(amount as i64), -((energy / 3) as i128)));
+    let (p, s) = build(delta.into());
+    assert_eq!(check_transfer(&p, &s, &*POOL_PARAMS), Ok(()));
+    assert_eq!(u128::try_from(s.tx.output.0.e.to_num()).unwrap(), energy - energy / 3);
+
+    assert_eq!(energy_withdrawal_delta(energy, energy + 1, 0, current_index, 0).err(), Some(EnergyError::InsufficientEnergy));
+    assert_eq!(energy_withdrawal_delta(1 << 112, (1 << 111) + 1, 0, current_index, 0).err(), Some(EnergyError::InvalidDelta(DeltaError::EnergyOutOfRange)));
+    assert_eq!(energy_withdrawal_delta(energy, 0, u64::MAX, current_index, 0).err(), Some(EnergyError::InvalidDelta(DeltaError::ValueOutOfRange)));
+    assert_eq!(energy_withdrawal_delta(energy, 0, 0, 1 << 48, 0).err(), Some(EnergyError::InvalidDelta(DeltaError::IndexOutOfRange)));
+
+    // delta can't withdraw more energy than the transaction has
+    let delta = Delta::new(0, -(energy as i128) - 1, current_index, 0).unwrap();
+    let res = notes.iter().fold(
+        TxBuilder::new(state.sigma, account, state.merkle_proof(state.account_id * 2), delta.into()),
+        |b, &(i, n)| b.input_note(n, state.merkle_proof(i as usize))
+    ).build(&mut rng, &*POOL_PARAMS);
+    assert_eq!(res.err(), Some(TxBuilderError::InvalidEnergy));
+}
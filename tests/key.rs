@@ -0,0 +1,96 @@
+use libzeropool::POOL_PARAMS;
+use libzeropool::native::{
+    key::{derive_key_a, derive_key_eta, derive_key_sigma, ViewingKey},
+    params::{PoolBN256, PoolParams},
+    address::Address,
+    note::Note,
+    tx::tx_sign,
+    cipher,
+};
+use libzeropool::helpers::sample_data::State;
+use std::str::FromStr;
+
+use libzeropool::fawkes_crypto::{
+    ff_uint::Num,
+    native::poseidon::poseidon,
+    rand::{thread_rng, Rng},
+    borsh::{BorshSerialize, BorshDeserialize},
+};
+use libzeropool::fawkes_crypto::engines::bn256::Fr;
+
+
+#[test]
+fn test_derive_key_sigma_vectors() {
+    let seed: Vec<u8> = (0..64).collect();
+    let vectors = [
+        (0u32, "1676688461538960410091696982762306957124185451833377509907712467404355399124", "21143826449119152131792935573752049867049377192054090709675317973577764156942"),
+        (1, "997426311227400038988742316962082892692264696263403610341568837459767643728", "12120791817504719883589975585032282859502376538476820454186518636604889285478"),
+        (0xffffffff, "1788846692842091813828516319842991742271425967880331407233457209195306892285", "500293325661463764032824385185709991756419317033712103279708882678706384155"),
+    ];
+
+    for &(account, sigma_str, eta_str) in vectors.iter() {
+        let sigma = derive_key_sigma::<PoolBN256>(&seed, account);
+        let eta = derive_key_eta(derive_key_a(sigma, &*POOL_PARAMS).x, &*POOL_PARAMS);
+        assert_eq!(sigma, Num::from_str(sigma_str).unwrap());
+        assert_eq!(eta, Num::from_str(eta_str).unwrap());
+    }
+
+    let sigma = derive_key_sigma::<PoolBN256>(&[0u8; 32], 0);
+    assert_eq!(sigma, Num::from_str("729803184346859884579239447473726617842422822222118407998075652908483382106").unwrap());
+    assert!(sigma != derive_key_sigma::<PoolBN256>(&[0u8; 33], 0));
+}
+
+#[test]
+fn test_viewing_key() {
+    let mut rng = thread_rng();
+    let state = State::random_sample_state(&mut rng, &*POOL_PARAMS);
+    let (p, _) = state.random_sample_transfer(&mut rng, &*POOL_PARAMS);
+
+    let vk = ViewingKey::from_sigma(state.sigma, &*POOL_PARAMS);
+    assert_eq!(vk.eta(), derive_key_eta(derive_key_a(state.sigma, &*POOL_PARAMS).x, &*POOL_PARAMS));
+    assert_eq!(ViewingKey::new(vk.a(), &*POOL_PARAMS), Some(vk));
+    // ovk is not the merkle node of a next to the empty leaf
+    assert!(vk.ovk(&*POOL_PARAMS) != poseidon(&[vk.a(), Num::ZERO], POOL_PARAMS.compress()));
+
+    let (account, _) = state.items[state.account_id];
+    let index = Num::from((state.account_id * 2) as u64);
+    assert_eq!(vk.nullifier(account.hash(&*POOL_PARAMS), index, &*POOL_PARAMS), p.nullifier);
+
+    let tx_hash: Num<Fr> = rng.gen();
+    let (s, r) = tx_sign(state.sigma, tx_hash, &*POOL_PARAMS);
+    assert!(vk.verify(s, r, tx_hash, &*POOL_PARAMS));
+    assert!(!vk.verify(s, r, tx_hash + Num::ONE, &*POOL_PARAMS));
+
+    let address = Address::generate(vk.eta(), &mut rng, &*POOL_PARAMS);
+    assert!(vk.is_own_address(&address, &*POOL_PARAMS));
+    let mut note: Note<Fr> = Note::sample(&mut rng, &*POOL_PARAMS);
+    note.d = address.d;
+    note.p_d = address.p_d;
+    let entropy: Vec<u8> = (0..32).map(|_| rng.gen()).collect();
+    let memo = cipher::encrypt(&entropy, vk.eta(), account, &[note], &*POOL_PARAMS);
+    assert_eq!(vk.decrypt_out(&memo, &*POOL_PARAMS), Some((account, vec![note])));
+    assert_eq!(vk.decrypt_in(&memo, &*POOL_PARAMS), vec![Some(note)]);
+
+    let vk_restored = ViewingKey::<Fr>::try_from_slice(&vk.try_to_vec().unwrap()).unwrap();
+    assert_eq!(vk_restored, vk);
+    let vk_restored: ViewingKey<Fr> = serde_json::from_str(&serde_json::to_string(&vk).unwrap()).unwrap();
+    assert_eq!(vk_restored, vk);
+
+    // eta is not derived from a
+    let mut data = vk.try_to_vec().unwrap();
+    data[40] ^= 1;
+    assert!(ViewingKey::<Fr>::try_from_slice(&data).is_err());
+    let json = format!("{{\"a\":{},\"eta\":{}}}", serde_json::to_string(&vk.a()).unwrap(), serde_json::to_string(&(vk.eta() + Num::ONE)).unwrap());
+    assert!(serde_json::from_str::<ViewingKey<Fr>>(&json).is_err());
+
+    // a is not on the JubJub subgroup
+    let a = loop {
+        let a: Num<Fr> = rng.gen();
+        if ViewingKey::new(a, &*POOL_PARAMS).is_none() {
+            break a;
+        }
+    };
+    let mut data = a.try_to_vec().unwrap();
+    data.extend(derive_key_eta(a, &*POOL_PARAMS).try_to_vec().unwrap());
+    assert!(ViewingKey::<Fr>::try_from_slice(&data).is_err());
+}
@@ -0,0 +1,103 @@
+use libzeropool::{POOL_PARAMS, constants};
+
+use libzeropool::fawkes_crypto::{ff_uint::Num, native::ecc::EdwardsPoint};
+
+use libzeropool::fawkes_crypto::rand::{thread_rng, Rng};
+use libzeropool::native::{
+    note::Note,
+    account::Account,
+    key::{derive_key_p_d, ViewingKey},
+    params::{PoolBN256, PoolParams},
+    scanner::{MemoScanner, ScannedMemo, Wnaf},
+    cipher::{self, MemoHeader}
+};
+
+use libzeropool::fawkes_crypto::engines::bn256::Fr;
+
+
+#[test]
+fn test_memo_scanner() {
+    let mut rng = thread_rng();
+    let owner = ViewingKey::from_sigma(rng.gen(), &*POOL_PARAMS);
+    let other = ViewingKey::from_sigma(rng.gen(), &*POOL_PARAMS);
+
+    let mut account: Account<Fr> = Account::sample(&mut rng, &*POOL_PARAMS);
+    account.p_d = derive_key_p_d(account.d.to_num(), owner.eta(), &*POOL_PARAMS).x;
+    let mut note: Vec<Note<Fr>> = (0..3).map(|_| Note::sample(&mut rng, &*POOL_PARAMS)).collect();
+    note[1].p_d = derive_key_p_d(note[1].d.to_num(), owner.eta(), &*POOL_PARAMS).x;
+    let mut entropy = || (0..32).map(|_| rng.gen()).collect::<Vec<u8>>();
+
+    let legacy = cipher::encrypt(&entropy(), owner.eta(), account, &note, &*POOL_PARAMS);
+    let versioned = cipher::encrypt_with_ovk(&entropy(), owner.ovk(&*POOL_PARAMS), account, &note, &*POOL_PARAMS);
+    let incoming = cipher::encrypt(&entropy(), other.eta(), account, &note, &*POOL_PARAMS);
+    let foreign = cipher::encrypt(&entropy(), other.eta(), account, &note[..1], &*POOL_PARAMS);
+    let mut corrupted = legacy.clone();
+    corrupted.truncate(100);
+    let header = MemoHeader::new(constants::MEMO_VERSION_LEGACY).with_detection_tags();
+    let tagged = cipher::encrypt_with_header(&entropy(), &header, other.eta(), account, &note, &*POOL_PARAMS);
+    let tagged_foreign = cipher::encrypt_with_header(&entropy(), &header, other.eta(), account, &note[..1], &*POOL_PARAMS);
+    let memos = vec![foreign, legacy, versioned, corrupted, incoming, tagged, tagged_foreign];
+
+    let scanner = MemoScanner::from_viewing_key(&owner, &*POOL_PARAMS);
+    let res = scanner.scan(&memos, &*POOL_PARAMS);
+    assert_eq!(res, vec![
+        ScannedMemo {index: 1, out: Some((account, note.clone())), in_notes: vec![(1, note[1])]},
+        ScannedMemo {index: 2, out: Some((account, note.clone())), in_notes: vec![(1, note[1])]},
+        ScannedMemo {index: 4, out: None, in_notes: vec![(1, note[1])]},
+        ScannedMemo {index: 5, out: None, in_notes: vec![(1, note[1])]},
+    ]);
+
+    for memo in memos.iter() {
+        assert_eq!(scanner.decrypt_in(memo, &*POOL_PARAMS), cipher::try_decrypt_in(owner.eta(), memo, &*POOL_PARAMS));
+        assert_eq!(scanner.decrypt_out(memo, &*POOL_PARAMS).ok(), owner.decrypt_out(memo, &*POOL_PARAMS));
+        assert_eq!(scanner.quick_check(memo, &*POOL_PARAMS), cipher::quick_check(owner.eta(), memo, &*POOL_PARAMS));
+    }
+    assert!(scanner.quick_check(&memos[5], &*POOL_PARAMS));
+    assert!(!scanner.quick_check(&memos[6], &*POOL_PARAMS));
+
+    // without ovk only legacy outgoing data is decrypted
+    let scanner = MemoScanner::new(owner.eta(), &*POOL_PARAMS);
+    let res = scanner.scan(&memos, &*POOL_PARAMS);
+    assert_eq!(res.iter().map(|m| (m.index, m.out.is_some())).collect::<Vec<_>>(), vec![(1, true), (2, false), (4, false), (5, false)]);
+}
+
+#[test]
+fn test_memo_scanner_random_keys() {
+    let mut rng = thread_rng();
+    let keys = (0..4).map(|_| ViewingKey::<Fr>::from_sigma(rng.gen(), &*POOL_PARAMS)).collect::<Vec<_>>();
+
+    for _ in 0..4 {
+        let account: Account<Fr> = Account::sample(&mut rng, &*POOL_PARAMS);
+        let note = (0..4).map(|_| {
+            let mut n: Note<Fr> = Note::sample(&mut rng, &*POOL_PARAMS);
+            n.p_d = derive_key_p_d(n.d.to_num(), keys[rng.gen_range(0, keys.len())].eta(), &*POOL_PARAMS).x;
+            n
+        }).collect::<Vec<_>>();
+        let sender = &keys[rng.gen_range(0, keys.len())];
+        let memo = cipher::encrypt(&rng.gen::<[u8; 32]>(), sender.eta(), account, &note, &*POOL_PARAMS);
+
+        for vk in keys.iter() {
+            let scanner = MemoScanner::from_viewing_key(vk, &*POOL_PARAMS);
+            assert_eq!(scanner.decrypt_in(&memo, &*POOL_PARAMS).unwrap(), cipher::decrypt_in(vk.eta(), &memo, &*POOL_PARAMS));
+            assert_eq!(scanner.decrypt_out(&memo, &*POOL_PARAMS).ok(), cipher::decrypt_out(vk.eta(), &memo, &*POOL_PARAMS));
+        }
+    }
+}
+
+#[test]
+fn test_wnaf_mul() {
+    type Fs = <PoolBN256 as PoolParams>::Fs;
+    let mut rng = thread_rng();
+    let jubjub = POOL_PARAMS.jubjub();
+
+    for _ in 0..20 {
+        let p = EdwardsPoint::<Fr>::rand(&mut rng, jubjub);
+        let scalar: Num<Fs> = rng.gen();
+        assert!(Wnaf::new(scalar.to_uint()).mul(&p.into_extended(), jubjub).into_affine() == p.mul(scalar, jubjub));
+        assert!(Wnaf::new(Num::<Fs>::MODULUS).mul(&p.into_extended(), jubjub).into_affine() ==
+            p.into_extended().mul(Num::<Fs>::MODULUS, jubjub).into_affine());
+    }
+    let p = EdwardsPoint::<Fr>::rand(&mut rng, jubjub);
+    assert!(Wnaf::new(Num::<Fs>::ZERO.to_uint()).mul(&p.into_extended(), jubjub).into_affine() == EdwardsPoint::zero());
+    assert!(Wnaf::new((-Num::<Fs>::ONE).to_uint()).mul(&p.into_extended(), jubjub).into_affine() == p.mul(-Num::ONE, jubjub));
+}
@@ -1,10 +1,12 @@
-use libzeropool::{POOL_PARAMS, circuit::tx::{CTransferPub, CTransferSec, c_transfer},
+use libzeropool::{POOL_PARAMS, circuit::tx::{CTransferPub, CTransferSec, c_parse_delta, c_transfer},
     fawkes_crypto::{
         circuit::{
-            cs::{CS, DebugCS}
+            cs::{CS, DebugCS},
+            num::CNum,
         }, 
         core::signal::Signal,
-        rand::thread_rng,
+        rand::{thread_rng, Rng},
+        borsh::{BorshSerialize, BorshDeserialize},
         backend::bellman_groth16::{
             engines::Bn256,
             setup::setup,
@@ -19,6 +21,12 @@ use std::time::Instant;
     
 
 use libzeropool::helpers::sample_data::State;
+use libzeropool::native::tx::{check_transfer, make_delta, memo_hash, nullifier, out_commitment_hash, parse_delta, try_make_delta, tx_hash, tx_sign, Delta, DeltaError, TransferError, TransferPub, TransferSec, TxBuilder, TxBuilderError, TxType};
+use libzeropool::native::{address::Address, boundednum::BoundedNum, cipher::{self, MemoHeader}, key::{derive_key_a, derive_key_eta, ViewingKey}, params::{PoolBN256, PoolParams}};
+use libzeropool::fawkes_crypto::native::poseidon::poseidon_merkle_proof_root;
+use libzeropool::constants;
+use std::convert::TryFrom;
+use libzeropool::fawkes_crypto::ff_uint::Num;
 
 
 #[test]
@@ -71,3 +79,352 @@ fn test_circuit_tx_setup_and_prove() {
     assert!(res, "Verifier result should be true");
 }
 
+
+
+// out_commit, nullifier and signature of the changed witness, so only the changed rule is broken
+fn resign(sigma:Num<<PoolBN256 as PoolParams>::Fs>, p:&mut TransferPub<Fr>, s:&mut TransferSec<Fr>) {
+    let params = &*POOL_PARAMS;
+    let in_account_hash = s.tx.input.0.hash(params);
+    let in_hash = std::iter::once(in_account_hash).chain(s.tx.input.1.iter().map(|n| n.hash(params))).collect::<Vec<_>>();
+    let out_hash = std::iter::once(s.tx.output.0.hash(params)).chain(s.tx.output.1.iter().map(|n| n.hash(params))).collect::<Vec<_>>();
+    let account_index = s.in_proof.0.path.iter().rev().fold(Num::ZERO, |acc, &b| acc.double() + if b { Num::ONE } else { Num::ZERO });
+
+    p.out_commit = out_commitment_hash::<_, { constants::OUT }>(&out_hash, params);
+    p.nullifier = nullifier(in_account_hash, derive_key_eta(s.eddsa_a, params), account_index, params);
+    let (eddsa_s, eddsa_r) = tx_sign(sigma, tx_hash(&in_hash, p.out_commit, params), params);
+    s.eddsa_s = eddsa_s.to_other().unwrap();
+    s.eddsa_r = eddsa_r;
+}
+
+fn circuit_accepts(p:&TransferPub<Fr>, s:&TransferSec<Fr>) -> bool {
+    std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        let cs = &DebugCS::rc_new();
+        let p = &CTransferPub::alloc(cs, Some(p));
+        let s = &CTransferSec::alloc(cs, Some(s));
+        c_transfer(p, s, &*POOL_PARAMS);
+    })).is_ok()
+}
+
+#[test]
+fn test_check_transfer() {
+    let mut rng = thread_rng();
+    let state = State::random_sample_state(&mut rng, &*POOL_PARAMS);
+    let (p, s) = state.random_sample_transfer(&mut rng, &*POOL_PARAMS);
+    assert_eq!(check_transfer(&p, &s, &*POOL_PARAMS), Ok(()));
+    assert!(circuit_accepts(&p, &s));
+
+    // every broken rule is rejected by check_transfer and c_transfer
+    let check = |p:&TransferPub<Fr>, s:&TransferSec<Fr>, err| {
+        assert_eq!(check_transfer(p, s, &*POOL_PARAMS), Err(err));
+        assert!(!circuit_accepts(p, s), "{:?}", err);
+    };
+    let (_, _, current_index, _) = parse_delta(p.delta);
+
+    let mut p_bad = p.clone();
+    p_bad.nullifier = rng.gen();
+    check(&p_bad, &s, TransferError::NullifierMismatch);
+
+    let mut p_bad = p.clone();
+    p_bad.root = rng.gen();
+    check(&p_bad, &s, TransferError::InputAccountRoot);
+
+    let mut p_bad = p.clone();
+    p_bad.out_commit = rng.gen();
+    check(&p_bad, &s, TransferError::OutCommitMismatch);
+
+    let mut s_bad = s.clone();
+    s_bad.eddsa_r = rng.gen();
+    check(&p, &s_bad, TransferError::InvalidSignature);
+
+    let mut s_bad = s.clone();
+    s_bad.tx.input.1[1] = s_bad.tx.input.1[0];
+    check(&p, &s_bad, TransferError::InputNotesNotUnique);
+
+    let mut s_bad = s.clone();
+    s_bad.tx.input.1[0].p_d = rng.gen();
+    check(&p, &s_bad, TransferError::InputNoteOwner(0));
+
+    let mut p_bad = p.clone();
+    let mut s_bad = s.clone();
+    s_bad.tx.output.1[1] = s_bad.tx.output.1[0];
+    resign(state.sigma, &mut p_bad, &mut s_bad);
+    check(&p_bad, &s_bad, TransferError::OutputNotesNotUnique);
+
+    let mut p_bad = p.clone();
+    let mut s_bad = s.clone();
+    s_bad.tx.input.0.p_d = rng.gen();
+    resign(state.sigma, &mut p_bad, &mut s_bad);
+    check(&p_bad, &s_bad, TransferError::InputAccountOwner);
+
+    let mut p_bad = p.clone();
+    let mut s_bad = s.clone();
+    s_bad.tx.output.0.p_d = rng.gen();
+    resign(state.sigma, &mut p_bad, &mut s_bad);
+    check(&p_bad, &s_bad, TransferError::OutputAccountOwner);
+
+    let mut s_bad = s.clone();
+    s_bad.in_proof.1[0].sibling[0] = rng.gen();
+    check(&p, &s_bad, TransferError::InputNoteRoot(0));
+
+    // input account with index above the output account, the root is rebuilt for it
+    let mut p_bad = p.clone();
+    let mut s_bad = s.clone();
+    s_bad.tx.input.0.i = BoundedNum::new(current_index + Num::ONE);
+    p_bad.root = poseidon_merkle_proof_root(s_bad.tx.input.0.hash(&*POOL_PARAMS), &s_bad.in_proof.0, POOL_PARAMS.compress());
+    resign(state.sigma, &mut p_bad, &mut s_bad);
+    check(&p_bad, &s_bad, TransferError::AccountIndexOrder);
+
+    let mut p_bad = p.clone();
+    let mut s_bad = s.clone();
+    s_bad.tx.output.0.i = BoundedNum::new(current_index + Num::ONE);
+    resign(state.sigma, &mut p_bad, &mut s_bad);
+    check(&p_bad, &s_bad, TransferError::CurrentIndexOrder);
+
+    // all input notes are older than the output account index
+    let mut p_bad = p.clone();
+    let mut s_bad = s.clone();
+    s_bad.tx.output.0.i = BoundedNum::new(Num::ZERO);
+    resign(state.sigma, &mut p_bad, &mut s_bad);
+    check(&p_bad, &s_bad, TransferError::InputNoteIndex(0));
+
+    let mut p_bad = p.clone();
+    p_bad.memo = -Num::ONE;
+    check(&p_bad, &s, TransferError::InvalidMemo);
+
+    let mut p_bad = p.clone();
+    let mut s_bad = s.clone();
+    s_bad.tx.output.0.b = BoundedNum::new(s_bad.tx.output.0.b.to_num() + Num::ONE);
+    resign(state.sigma, &mut p_bad, &mut s_bad);
+    check(&p_bad, &s_bad, TransferError::BalanceMismatch);
+
+    let mut p_bad = p.clone();
+    let mut s_bad = s.clone();
+    s_bad.tx.output.0.e = BoundedNum::new(s_bad.tx.output.0.e.to_num() + Num::ONE);
+    resign(state.sigma, &mut p_bad, &mut s_bad);
+    check(&p_bad, &s_bad, TransferError::NegativeEnergy);
+}
+
+#[test]
+fn test_tx_builder() {
+    let mut rng = thread_rng();
+    let state = State::random_sample_state(&mut rng, &*POOL_PARAMS);
+    let (account, _) = state.items[state.account_id];
+    let (_, note) = state.items[state.note_id[0]];
+    let account_proof = state.merkle_proof(state.account_id*2);
+    let note_proof = state.merkle_proof(state.note_id[0]*2+1);
+    let out_address = Address::generate(rng.gen(), &mut rng, &*POOL_PARAMS);
+    let delta = make_delta(Num::ZERO, Num::ZERO, Num::from(state.items.len() as u64 * 2), Num::ZERO);
+
+    let builder = TxBuilder::new(state.sigma, account, account_proof, delta)
+        .input_note(note, note_proof)
+        .output(out_address, note.b)
+        .memo(rng.gen());
+
+    let (p, s) = builder.build(&mut rng, &*POOL_PARAMS).unwrap();
+    assert_eq!(check_transfer(&p, &s, &*POOL_PARAMS), Ok(()));
+    assert!(s.tx.output.0.b == account.b);
+
+    let cs = &DebugCS::rc_new();
+    let p = &CTransferPub::alloc(cs, Some(&p));
+    let s = &CTransferSec::alloc(cs, Some(&s));
+    c_transfer(p, s, &*POOL_PARAMS);
+
+    let builder = builder.output(out_address, BoundedNum::new(account.b.to_num() + Num::ONE));
+    assert_eq!(builder.build(&mut rng, &*POOL_PARAMS).err(), Some(TxBuilderError::InvalidBalance));
+}
+
+#[test]
+fn test_tx_builder_shape() {
+    let mut rng = thread_rng();
+    let state = State::random_sample_state(&mut rng, &*POOL_PARAMS);
+    let (account, _) = state.items[state.account_id];
+    let (_, note) = state.items[state.note_id[0]];
+    let account_proof = state.merkle_proof(state.account_id*2);
+    let note_proof = state.merkle_proof(state.note_id[0]*2+1);
+    let delta = make_delta(Num::ZERO, Num::ZERO, Num::from(state.items.len() as u64 * 2), Num::ZERO);
+
+    let builder = TxBuilder::<PoolBN256, 1, 127>::new_with_shape(state.sigma, account, account_proof, delta)
+        .input_note(note, note_proof.clone());
+    let (p, s) = builder.clone().build(&mut rng, &*POOL_PARAMS).unwrap();
+    assert_eq!(s.tx.input.1.iter().count(), 1);
+    assert_eq!(check_transfer(&p, &s, &*POOL_PARAMS), Ok(()));
+
+    let cs = &DebugCS::rc_new();
+    let p = &CTransferPub::alloc(cs, Some(&p));
+    let s = &CTransferSec::<_, 1, 127>::alloc(cs, Some(&s));
+    c_transfer(p, s, &*POOL_PARAMS);
+
+    let builder = builder.input_note(note, note_proof);
+    assert_eq!(builder.build(&mut rng, &*POOL_PARAMS).err(), Some(TxBuilderError::TooManyInputs));
+
+    // memos are built only for constants::OUT
+    let builder = TxBuilder::<PoolBN256, 1, 63>::new_with_shape(state.sigma, account, state.merkle_proof(state.account_id*2), delta);
+    assert_eq!(builder.build_with_memo(&mut rng, &*POOL_PARAMS).err(), Some(TxBuilderError::UnsupportedShape));
+}
+
+#[test]
+fn test_transfer_tree_height() {
+    let mut rng = thread_rng();
+    let state = State::<_, 32>::random_sample_state_with_height(&mut rng, &*POOL_PARAMS);
+    let (p, s) = state.random_sample_transfer(&mut rng, &*POOL_PARAMS);
+    assert_eq!(s.in_proof.0.sibling.iter().count(), 32);
+    assert_eq!(check_transfer(&p, &s, &*POOL_PARAMS), Ok(()));
+
+    let cs = &DebugCS::rc_new();
+    let p = &CTransferPub::alloc(cs, Some(&p));
+    let s = &CTransferSec::<_, { constants::IN }, { constants::OUT }, 32>::alloc(cs, Some(&s));
+    c_transfer(p, s, &*POOL_PARAMS);
+
+    // proofs of the default height don't match the root of the shorter tree
+    let default_state = State::random_sample_state(&mut rng, &*POOL_PARAMS);
+    let (mut p, s) = default_state.random_sample_transfer(&mut rng, &*POOL_PARAMS);
+    p.root = state.root();
+    assert_eq!(check_transfer(&p, &s, &*POOL_PARAMS), Err(TransferError::InputAccountRoot));
+}
+
+#[test]
+fn test_tx_type_delta() {
+    let index = Num::from(128u64);
+    let poolid = Num::from(7u64);
+
+    let delta = TxType::Deposit { amount: 1000 }.delta::<Fr>(index, poolid).unwrap();
+    assert_eq!(parse_delta(delta), (Num::from(1000u64), Num::ZERO, index, poolid));
+
+    let delta = TxType::Withdraw { amount: 1000, energy: 50 }.delta::<Fr>(index, poolid).unwrap();
+    assert_eq!(parse_delta(delta), (-Num::from(1000u64), -Num::from(50u64), index, poolid));
+
+    let delta = TxType::Transfer.delta::<Fr>(index, poolid).unwrap();
+    assert_eq!(delta, make_delta(Num::ZERO, Num::ZERO, index, poolid));
+
+    assert_eq!(TxType::Deposit { amount: u64::MAX }.delta::<Fr>(index, poolid), Err(DeltaError::ValueOutOfRange));
+    assert_eq!(TxType::Withdraw { amount: 0, energy: 1 << 112 }.delta::<Fr>(index, poolid), Err(DeltaError::EnergyOutOfRange));
+    assert_eq!(try_make_delta::<Fr>(Num::ZERO, Num::ZERO, Num::from(1u64 << 48), poolid), Err(DeltaError::IndexOutOfRange));
+    assert_eq!(try_make_delta::<Fr>(Num::ZERO, Num::ZERO, index, Num::from(1u64 << 24)), Err(DeltaError::PoolIdOutOfRange));
+
+    let input = Num::<Fr>::from(500u64);
+    assert_eq!(TxType::Deposit { amount: 1000 }.output_balance(input, Num::from(200u64)).unwrap().to_num(), Num::from(1300u64));
+    assert_eq!(TxType::Withdraw { amount: 400, energy: 0 }.output_balance(input, Num::from(100u64)).unwrap().to_num(), Num::ZERO);
+    assert_eq!(TxType::Withdraw { amount: 400, energy: 0 }.output_balance(input, Num::from(101u64)).err(), Some(DeltaError::BalanceOutOfRange));
+    assert_eq!(TxType::Transfer.output_balance(Num::<Fr>::from(u64::MAX), Num::ZERO).unwrap().to_num(), Num::from(u64::MAX));
+}
+
+#[test]
+fn test_tx_builder_deposit() {
+    let mut rng = thread_rng();
+    let state = State::random_sample_state(&mut rng, &*POOL_PARAMS);
+    let (account, _) = state.items[state.account_id];
+    let (_, note) = state.items[state.note_id[0]];
+    let account_proof = state.merkle_proof(state.account_id*2);
+    let note_proof = state.merkle_proof(state.note_id[0]*2+1);
+    let tx_type = TxType::Deposit { amount: 1000 };
+    let delta = tx_type.delta(Num::from(state.items.len() as u64 * 2), Num::ZERO).unwrap();
+
+    let (p, s) = TxBuilder::new(state.sigma, account, account_proof, delta)
+        .input_note(note, note_proof)
+        .build(&mut rng, &*POOL_PARAMS).unwrap();
+    assert_eq!(check_transfer(&p, &s, &*POOL_PARAMS), Ok(()));
+    assert!(s.tx.output.0.b == tx_type.output_balance(account.b.to_num() + note.b.to_num(), Num::ZERO).unwrap());
+}
+
+#[test]
+fn test_delta_parse() {
+    let mut rng = thread_rng();
+    let mut deltas = vec![
+        Delta::new(0, 0, 0, 0).unwrap(),
+        Delta::new(i64::MIN, -(1 << 111), (1 << 48) - 1, (1 << 24) - 1).unwrap(),
+        Delta::new(i64::MAX, (1 << 111) - 1, 0, 0).unwrap(),
+        Delta::new(-1, -1, 1, 1).unwrap(),
+    ];
+    for _ in 0..10 {
+        deltas.push(Delta::new(rng.gen(), rng.gen::<i128>() >> 17, rng.gen::<u64>() >> 16, rng.gen::<u32>() >> 8).unwrap());
+    }
+
+    for delta in deltas {
+        let n: Num<Fr> = delta.into();
+        assert_eq!(Delta::try_from(n), Ok(delta));
+
+        let cs = &DebugCS::rc_new();
+        let signal = CNum::alloc(cs, Some(&n));
+        let (v, e, index, pool_id) = c_parse_delta::<_, PoolBN256>(&signal);
+        assert_eq!(v.get_value(), Some(Num::from(delta.value() as i128)));
+        assert_eq!(e.get_value(), Some(Num::from(delta.energy())));
+        assert_eq!(index.get_value(), Some(Num::from(delta.index())));
+        assert_eq!(pool_id.get_value(), Some(Num::from(delta.pool_id() as u64)));
+
+        assert_eq!(Delta::try_from_slice(&delta.try_to_vec().unwrap()).unwrap(), delta);
+        assert_eq!(serde_json::from_str::<Delta>(&serde_json::to_string(&delta).unwrap()).unwrap(), delta);
+    }
+
+    assert_eq!(Delta::new(0, 1 << 111, 0, 0), Err(DeltaError::EnergyOutOfRange));
+    assert_eq!(Delta::new(0, 0, 1 << 48, 0), Err(DeltaError::IndexOutOfRange));
+    assert_eq!(Delta::new(0, 0, 0, 1 << 24), Err(DeltaError::PoolIdOutOfRange));
+    assert_eq!(Delta::try_from(-Num::<Fr>::ONE), Err(DeltaError::InvalidDelta));
+    assert!(serde_json::from_str::<Delta>(r#"{"value":0,"energy":0,"index":0,"pool_id":16777216}"#).is_err());
+    assert!(Delta::try_from_slice(&(0i64, 0i128, 1u64 << 48, 0u32).try_to_vec().unwrap()).is_err());
+}
+
+#[test]
+fn test_memo_hash() {
+    // keccak256 of empty string reduced by the field modulus
+    assert_eq!(memo_hash::<Fr>(&[]), "1924180730567573949438414972962865885128629851683618892617351438379423999084".parse().unwrap());
+
+    let mut rng = thread_rng();
+    let state = State::random_sample_state(&mut rng, &*POOL_PARAMS);
+    let (p, s) = state.random_sample_transfer(&mut rng, &*POOL_PARAMS);
+    assert_eq!(check_transfer(&p, &s, &*POOL_PARAMS), Ok(()));
+
+    let (account, _) = state.items[state.account_id];
+    let account_proof = state.merkle_proof(state.account_id*2);
+    let delta = make_delta::<Fr>(Num::ZERO, Num::ZERO, Num::from(state.items.len() as u64 * 2), Num::ZERO);
+    let out_address = Address::generate(rng.gen(), &mut rng, &*POOL_PARAMS);
+    let (p, s, memo) = TxBuilder::new(state.sigma, account, account_proof, delta)
+        .output(out_address, BoundedNum::new(Num::ZERO))
+        .build_with_memo(&mut rng, &*POOL_PARAMS).unwrap();
+    assert_eq!(check_transfer(&p, &s, &*POOL_PARAMS), Ok(()));
+    assert_eq!(p.memo, memo_hash(&memo));
+
+    let eta = derive_key_eta(derive_key_a(state.sigma, &*POOL_PARAMS).x, &*POOL_PARAMS);
+    let (out_account, out_notes) = cipher::decrypt_out(eta, &memo, &*POOL_PARAMS).unwrap();
+    assert!(out_account == s.tx.output.0);
+    assert!(out_notes.len() == 1 && out_notes[0] == s.tx.output.1[0]);
+
+    // memo with header, fee and hardened cipher is bound as well
+    let mut header = MemoHeader::new(constants::MEMO_VERSION_OVK).with_detection_tags().with_hardened_cipher();
+    header.fee = Some(100);
+    let builder = TxBuilder::new(state.sigma, account, state.merkle_proof(state.account_id*2), delta)
+        .output(out_address, BoundedNum::new(Num::ZERO))
+        .memo_header(header);
+    let (p, s, memo) = builder.build_with_memo(&mut rng, &*POOL_PARAMS).unwrap();
+    assert_eq!(check_transfer(&p, &s, &*POOL_PARAMS), Ok(()));
+    assert_eq!(p.memo, memo_hash(&memo));
+    let (memo_header, _) = cipher::parse_memo_header(&memo).unwrap();
+    assert!(memo_header.hardened && memo_header.fee == Some(100) && memo_header.detection_tags.unwrap().len() == 1);
+    let vk = ViewingKey::from_sigma(state.sigma, &*POOL_PARAMS);
+    assert_eq!(cipher::decrypt_out_with_ovk(vk.ovk(&*POOL_PARAMS), &memo, &*POOL_PARAMS), Some((s.tx.output.0, vec![s.tx.output.1[0]])));
+    assert_eq!(cipher::decrypt_out(eta, &memo, &*POOL_PARAMS), None);
+
+    assert_eq!(builder.clone().memo(rng.gen()).build_with_memo(&mut rng, &*POOL_PARAMS).err(), Some(TxBuilderError::MemoConflict));
+    assert_eq!(builder.memo_header(MemoHeader::new(2)).build_with_memo(&mut rng, &*POOL_PARAMS).err(), Some(TxBuilderError::UnknownMemoVersion));
+}
+
+#[test]
+fn test_memo_commitment() {
+    let mut rng = thread_rng();
+    let state = State::random_sample_state(&mut rng, &*POOL_PARAMS);
+    let (account, _) = state.items[state.account_id];
+    let account_proof = state.merkle_proof(state.account_id*2);
+    let delta = make_delta::<Fr>(Num::ZERO, Num::ZERO, Num::from(state.items.len() as u64 * 2), Num::ZERO);
+    let out_address = Address::generate(rng.gen(), &mut rng, &*POOL_PARAMS);
+    let (p, s, memo) = TxBuilder::new(state.sigma, account, account_proof, delta)
+        .output(out_address, BoundedNum::new(Num::ZERO))
+        .output(out_address, BoundedNum::new(Num::ZERO))
+        .build_with_memo(&mut rng, &*POOL_PARAMS).unwrap();
+    assert!(cipher::verify_memo_commitment(&memo, p.out_commit, &*POOL_PARAMS));
+    assert!(!cipher::verify_memo_commitment(&memo, p.out_commit + Num::ONE, &*POOL_PARAMS));
+    assert!(!cipher::verify_memo_commitment(&memo[..memo.len() - 1], p.out_commit, &*POOL_PARAMS));
+
+    // memo describing only a part of the outputs
+    let eta = derive_key_eta(derive_key_a(state.sigma, &*POOL_PARAMS).x, &*POOL_PARAMS);
+    let partial = cipher::encrypt(&rng.gen::<[u8; 32]>(), eta, s.tx.output.0, &s.tx.output.1.as_slice()[..1], &*POOL_PARAMS);
+    assert!(!cipher::verify_memo_commitment(&partial, p.out_commit, &*POOL_PARAMS));
+}
@@ -1,8 +1,11 @@
 use fawkes_crypto::rand::Rng;
 use libzeropool::{POOL_PARAMS, circuit::tree::{CTreePub, CTreeSec, tree_update},
-    native::tree::{TreePub, TreeSec},
+    native::{tree::{TreePub, TreeSec, MerkleTree, make_tree_update, tree_update_check, TreeUpdateError}, storage::{FileStorage, MemoryStorage}, params::PoolParams},
+    constants::OUTPLUSONELOG,
     fawkes_crypto::{
         ff_uint::Num,
+        engines::bn256::Fr,
+        native::poseidon::poseidon_merkle_proof_root,
         circuit::{
             cs::{CS, DebugCS}
         }, 
@@ -90,3 +93,320 @@ fn test_circuit_tx_fullfill_empty(){
     println!("Time elapsed in c_transfer() is: {:?}", duration);
 }
 
+#[test]
+fn test_make_tree_update_not_empty(){
+    let mut rng = thread_rng();
+    let mut state = HashTreeState::new(&*POOL_PARAMS);
+    let mut tree = MerkleTree::new(&*POOL_PARAMS);
+    let num_elements:usize = rng.gen_range(1, 1000);
+
+    for _ in 0..num_elements {
+        let leaf = rng.gen();
+        state.push(leaf, &*POOL_PARAMS);
+        tree.append_commitment(leaf, &*POOL_PARAMS).unwrap();
+    }
+
+    let root_before = state.root();
+    let proof_filled = state.merkle_proof(num_elements-1);
+    let proof_free = state.merkle_proof(num_elements);
+    let prev_leaf = *state.hashes[0].last().unwrap();
+    let leaf = rng.gen();
+    state.push(leaf, &*POOL_PARAMS);
+    let root_after = state.root();
+
+    let (p, s) = make_tree_update(&mut tree, leaf, &*POOL_PARAMS).unwrap();
+    assert_eq!((p.root_before, p.root_after, p.leaf), (root_before, root_after, leaf));
+    assert_eq!(s.prev_leaf, prev_leaf);
+    assert_eq!(serde_json::to_string(&s.proof_filled).unwrap(), serde_json::to_string(&proof_filled).unwrap());
+    assert_eq!(serde_json::to_string(&s.proof_free).unwrap(), serde_json::to_string(&proof_free).unwrap());
+
+    let cs = &DebugCS::rc_new();
+    let p = &CTreePub::alloc(cs, Some(&p));
+    let s = &CTreeSec::alloc(cs, Some(&s));
+    tree_update(p, s, &*POOL_PARAMS);
+}
+
+#[test]
+fn test_make_tree_update_empty(){
+    let mut rng = thread_rng();
+    let mut state = HashTreeState::new(&*POOL_PARAMS);
+    let mut tree = MerkleTree::new(&*POOL_PARAMS);
+
+    let root_before = state.root();
+    let proof_free = state.merkle_proof(0);
+    let leaf = rng.gen();
+    state.push(leaf, &*POOL_PARAMS);
+    let root_after = state.root();
+
+    let (p, s) = make_tree_update(&mut tree, leaf, &*POOL_PARAMS).unwrap();
+    assert_eq!((p.root_before, p.root_after, p.leaf), (root_before, root_after, leaf));
+    assert_eq!(s.prev_leaf, Num::ZERO);
+    assert_eq!(serde_json::to_string(&s.proof_free).unwrap(), serde_json::to_string(&proof_free).unwrap());
+
+    let cs = &DebugCS::rc_new();
+    let p = &CTreePub::alloc(cs, Some(&p));
+    let s = &CTreeSec::alloc(cs, Some(&s));
+    tree_update(p, s, &*POOL_PARAMS);
+}
+
+#[test]
+fn test_circuit_tree_update_after_leafs(){
+    let mut rng = thread_rng();
+    let mut tree = MerkleTree::new(&*POOL_PARAMS);
+
+    for _ in 0..rng.gen_range(1, 1000) {
+        tree.append(rng.gen(), &*POOL_PARAMS).unwrap();
+    }
+
+    let (p, s) = make_tree_update(&mut tree, rng.gen(), &*POOL_PARAMS).unwrap();
+    assert_eq!(p.root_after, tree.root());
+
+    let cs = &DebugCS::rc_new();
+    let p = &CTreePub::alloc(cs, Some(&p));
+    let s = &CTreeSec::alloc(cs, Some(&s));
+    tree_update(p, s, &*POOL_PARAMS);
+}
+
+#[test]
+fn test_merkle_tree_proofs() {
+    let mut rng = thread_rng();
+    let mut tree = MerkleTree::new(&*POOL_PARAMS);
+    let leafs:Vec<Num<Fr>> = (0..rng.gen_range(1, 300)).map(|_| rng.gen()).collect();
+
+    for (i, &leaf) in leafs.iter().enumerate() {
+        assert_eq!(tree.append(leaf, &*POOL_PARAMS).unwrap(), i as u64);
+    }
+
+    let root = tree.root();
+    for (i, &leaf) in leafs.iter().enumerate() {
+        let proof = tree.merkle_proof(i as u64);
+        assert_eq!(poseidon_merkle_proof_root(leaf, &proof, POOL_PARAMS.compress()), root);
+    }
+
+    let proof = tree.merkle_proof(leafs.len() as u64);
+    assert_eq!(poseidon_merkle_proof_root(Num::ZERO, &proof, POOL_PARAMS.compress()), root);
+}
+
+#[test]
+fn test_merkle_tree_commitments() {
+    let mut rng = thread_rng();
+    let mut tree = MerkleTree::new(&*POOL_PARAMS);
+    let mut state = HashTreeState::new(&*POOL_PARAMS);
+    assert_eq!(tree.root(), state.root());
+
+    for i in 0..rng.gen_range(1, 100) {
+        let leaf:Num<Fr> = rng.gen();
+        assert_eq!(tree.append_commitment(leaf, &*POOL_PARAMS).unwrap(), i);
+        state.push(leaf, &*POOL_PARAMS);
+        assert_eq!(tree.root(), state.root());
+        assert_eq!(tree.next_index(), (i + 1) << OUTPLUSONELOG);
+    }
+
+    for i in 0..state.hashes[0].len() + 1 {
+        let l = tree.commitment_proof(i as u64);
+        let r = state.merkle_proof(i);
+        assert!(l.sibling.iter().zip(r.sibling.iter()).all(|(a, b)| a == b));
+        assert!(l.path.iter().zip(r.path.iter()).all(|(a, b)| a == b));
+    }
+}
+
+#[test]
+fn test_merkle_tree_rollback() {
+    let mut rng = thread_rng();
+    let mut tree = MerkleTree::new(&*POOL_PARAMS);
+    let num_before = rng.gen_range(0, 300);
+
+    for _ in 0..num_before {
+        tree.append(rng.gen(), &*POOL_PARAMS).unwrap();
+    }
+    let root_before = tree.root();
+    let proof_before = tree.merkle_proof(num_before);
+
+    for _ in 0..rng.gen_range(1, 300) {
+        tree.append(rng.gen(), &*POOL_PARAMS).unwrap();
+    }
+    tree.append_commitment(rng.gen(), &*POOL_PARAMS).unwrap();
+    assert!(tree.root() != root_before);
+
+    tree.rollback(num_before, &*POOL_PARAMS).unwrap();
+    assert_eq!(tree.next_index(), num_before);
+    assert_eq!(tree.root(), root_before);
+    let proof = tree.merkle_proof(num_before);
+    assert!(proof.sibling.iter().zip(proof_before.sibling.iter()).all(|(a, b)| a == b));
+
+    let err = tree.rollback(num_before + 1, &*POOL_PARAMS).unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+    assert_eq!(tree.next_index(), num_before);
+
+    tree.rollback(0, &*POOL_PARAMS).unwrap();
+    assert_eq!(tree.root(), MerkleTree::new(&*POOL_PARAMS).root());
+
+    // leaves of the transaction appended by commitment are unknown
+    tree.append_commitment(rng.gen(), &*POOL_PARAMS).unwrap();
+    let root = tree.root();
+    let err = tree.rollback(rng.gen_range(1, 1 << OUTPLUSONELOG), &*POOL_PARAMS).unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+    assert_eq!(tree.next_index(), 1 << OUTPLUSONELOG);
+    assert_eq!(tree.root(), root);
+}
+
+#[test]
+fn test_merkle_tree_file_storage() {
+    let mut rng = thread_rng();
+    let path = std::env::temp_dir().join(format!("libzeropool_tree_{}.log", rng.gen::<u64>()));
+
+    let mut tree = MerkleTree::new(&*POOL_PARAMS);
+    let mut file_tree = MerkleTree::new_with_storage(FileStorage::open(&path).unwrap(), &*POOL_PARAMS);
+
+    for _ in 0..rng.gen_range(1, 100) {
+        let leaf:Num<Fr> = rng.gen();
+        tree.append(leaf, &*POOL_PARAMS).unwrap();
+        file_tree.append(leaf, &*POOL_PARAMS).unwrap();
+    }
+    let commitment:Num<Fr> = rng.gen();
+    tree.append_commitment(commitment, &*POOL_PARAMS).unwrap();
+    file_tree.append_commitment(commitment, &*POOL_PARAMS).unwrap();
+    // rollback to the transaction boundary before the commitment
+    let tx_boundary = tree.next_index() - (1 << OUTPLUSONELOG);
+    tree.rollback(tx_boundary, &*POOL_PARAMS).unwrap();
+    file_tree.rollback(tx_boundary, &*POOL_PARAMS).unwrap();
+    drop(file_tree);
+
+    let file_tree = MerkleTree::new_with_storage(FileStorage::open(&path).unwrap(), &*POOL_PARAMS);
+    assert_eq!(file_tree.next_index(), tree.next_index());
+    assert_eq!(file_tree.root(), tree.root());
+    for i in 0..tree.next_index() + 1 {
+        assert_eq!(serde_json::to_string(&file_tree.merkle_proof(i)).unwrap(), serde_json::to_string(&tree.merkle_proof(i)).unwrap());
+    }
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_file_storage_recovery() {
+    let mut rng = thread_rng();
+    let path = std::env::temp_dir().join(format!("libzeropool_tree_{}.log", rng.gen::<u64>()));
+
+    let mut file_tree = MerkleTree::new_with_storage(FileStorage::open(&path).unwrap(), &*POOL_PARAMS);
+    for _ in 0..10 {
+        file_tree.append(rng.gen(), &*POOL_PARAMS).unwrap();
+    }
+    let root = file_tree.root();
+    file_tree.append(rng.gen(), &*POOL_PARAMS).unwrap();
+    drop(file_tree);
+
+    // interrupted write of the last record is discarded
+    let len = std::fs::metadata(&path).unwrap().len();
+    std::fs::OpenOptions::new().write(true).open(&path).unwrap().set_len(len - 5).unwrap();
+    let file_tree = MerkleTree::new_with_storage(FileStorage::open(&path).unwrap(), &*POOL_PARAMS);
+    assert_eq!(file_tree.next_index(), 10);
+    assert_eq!(file_tree.root(), root);
+    drop(file_tree);
+
+    // garbled last record is discarded too
+    let mut data = std::fs::read(&path).unwrap();
+    *data.last_mut().unwrap() ^= 1;
+    std::fs::write(&path, &data).unwrap();
+    let file_tree = MerkleTree::new_with_storage(FileStorage::open(&path).unwrap(), &*POOL_PARAMS);
+    assert_eq!(file_tree.next_index(), 9);
+    drop(file_tree);
+
+    // broken length or body in the middle of the log is an error, the log is kept
+    let data = std::fs::read(&path).unwrap();
+    for pos in [0, data.len() / 2].iter() {
+        let mut data = data.clone();
+        data[*pos] ^= 1;
+        std::fs::write(&path, &data).unwrap();
+        let err = FileStorage::<Fr>::open(&path).err().unwrap();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+        assert_eq!(std::fs::read(&path).unwrap(), data);
+    }
+
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_tree_update_check() {
+    let mut rng = thread_rng();
+    let mut tree = MerkleTree::new(&*POOL_PARAMS);
+
+    let (p, s) = make_tree_update(&mut tree, rng.gen(), &*POOL_PARAMS).unwrap();
+    assert_eq!(tree_update_check(&p, &s, &*POOL_PARAMS), Ok(()));
+
+    let mut tree_bad = MerkleTree::new(&*POOL_PARAMS);
+    let mut s_bad = s.clone();
+    s_bad.proof_free = tree_bad.commitment_proof(1);
+    tree_bad.append_commitment(tree_bad.default_hash(OUTPLUSONELOG), &*POOL_PARAMS).unwrap();
+    tree_bad.append_commitment(p.leaf, &*POOL_PARAMS).unwrap();
+    let mut p_bad = p.clone();
+    p_bad.root_after = tree_bad.root();
+    assert_eq!(tree_update_check(&p_bad, &s_bad, &*POOL_PARAMS), Err(TreeUpdateError::EmptyTreeIndex));
+
+    for _ in 0..rng.gen_range(1, 100) {
+        tree.append_commitment(rng.gen(), &*POOL_PARAMS).unwrap();
+    }
+
+    // valid proof of the commitment before the previous one
+    let index_skipped = (tree.next_index() >> OUTPLUSONELOG) - 2;
+    let proof_skipped = tree.commitment_proof(index_skipped);
+    let leaf_skipped = tree.node(OUTPLUSONELOG, index_skipped);
+
+    let (p, s) = make_tree_update(&mut tree, rng.gen(), &*POOL_PARAMS).unwrap();
+    assert_eq!(tree_update_check(&p, &s, &*POOL_PARAMS), Ok(()));
+
+    let mut s_bad = s.clone();
+    s_bad.proof_filled = proof_skipped;
+    s_bad.prev_leaf = leaf_skipped;
+    assert_eq!(tree_update_check(&p, &s_bad, &*POOL_PARAMS), Err(TreeUpdateError::PrevLeafIndex));
+
+    let mut p_bad = p.clone();
+    p_bad.root_before = rng.gen();
+    assert_eq!(tree_update_check(&p_bad, &s, &*POOL_PARAMS), Err(TreeUpdateError::ZeroLeafProof));
+
+    let mut p_bad = p.clone();
+    p_bad.leaf = rng.gen();
+    assert_eq!(tree_update_check(&p_bad, &s, &*POOL_PARAMS), Err(TreeUpdateError::RootAfterProof));
+
+    let mut s_bad = s.clone();
+    s_bad.prev_leaf = rng.gen();
+    assert_eq!(tree_update_check(&p, &s_bad, &*POOL_PARAMS), Err(TreeUpdateError::PrevLeafProof));
+
+    // previous commitment equal to the empty subtree hash
+    tree.append_commitment(tree.default_hash(OUTPLUSONELOG), &*POOL_PARAMS).unwrap();
+    let (p, s) = make_tree_update(&mut tree, rng.gen(), &*POOL_PARAMS).unwrap();
+    assert_eq!(tree_update_check(&p, &s, &*POOL_PARAMS), Err(TreeUpdateError::PrevLeafZero));
+}
+
+#[test]
+fn test_merkle_tree_height() {
+    let mut rng = thread_rng();
+    let mut state = HashTreeState::<_, 25>::new_with_height(&*POOL_PARAMS);
+    let mut tree = MerkleTree::<_, _, 32, 25>::new_with_height(MemoryStorage::new(), &*POOL_PARAMS);
+    assert_eq!(tree.root(), state.root());
+
+    for _ in 0..rng.gen_range(1, 100) {
+        let leaf = rng.gen();
+        state.push(leaf, &*POOL_PARAMS);
+        tree.append_commitment(leaf, &*POOL_PARAMS).unwrap();
+    }
+    assert_eq!(tree.root(), state.root());
+
+    let leaves = (0..3).map(|_| rng.gen()).collect::<Vec<_>>();
+    let indexes = leaves.iter().map(|&leaf| tree.append(leaf, &*POOL_PARAMS).unwrap()).collect::<Vec<_>>();
+    let proof = tree.merkle_proof(indexes[1]);
+    assert_eq!(proof.sibling.iter().count(), 32);
+    assert_eq!(poseidon_merkle_proof_root(leaves[1], &proof, POOL_PARAMS.compress()), tree.root());
+
+    let (p, s) = make_tree_update(&mut tree, rng.gen(), &*POOL_PARAMS).unwrap();
+    assert_eq!(p.root_after, tree.root());
+    assert_eq!(tree_update_check(&p, &s, &*POOL_PARAMS), Ok(()));
+
+    let cs = &DebugCS::rc_new();
+    let p = &CTreePub::alloc(cs, Some(&p));
+    let s = &CTreeSec::alloc(cs, Some(&s));
+    tree_update(p, s, &*POOL_PARAMS);
+
+    // the first update of an empty tree checks the zero root of the same height
+    let mut tree = MerkleTree::<_, _, 32, 25>::new_with_height(MemoryStorage::new(), &*POOL_PARAMS);
+    let (p, s) = make_tree_update(&mut tree, rng.gen(), &*POOL_PARAMS).unwrap();
+    assert_eq!(tree_update_check(&p, &s, &*POOL_PARAMS), Ok(()));
+}
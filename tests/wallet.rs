@@ -0,0 +1,170 @@
+use libzeropool::{POOL_PARAMS, constants};
+
+use libzeropool::fawkes_crypto::rand::{thread_rng, Rng};
+use libzeropool::fawkes_crypto::ff_uint::Num;
+use libzeropool::fawkes_crypto::native::poseidon::poseidon_merkle_proof_root;
+use libzeropool::native::{
+    note::Note,
+    account::Account,
+    address::Address,
+    boundednum::BoundedNum,
+    key::ViewingKey,
+    cipher::{self, MemoView},
+    params::{PoolBN256, PoolParams},
+    tx::{check_transfer, make_delta, out_commitment_hash, TxBuilder, TxBuilderError},
+    planner::{plan_inputs, plan_inputs_with_shape, PlanError, PlannedTx},
+    wallet::{Wallet, WalletError},
+};
+
+use libzeropool::fawkes_crypto::engines::bn256::Fr;
+
+
+fn out_commit(memo:&[u8]) -> Num<Fr> {
+    let zero_note_hash = Note::zero().hash(&*POOL_PARAMS);
+    let mut hashes = MemoView::<Fr>::parse(memo).unwrap().item_hashes();
+    hashes.resize(constants::OUT + 1, zero_note_hash);
+    out_commitment_hash::<_, { constants::OUT }>(&hashes, &*POOL_PARAMS)
+}
+
+#[test]
+fn test_wallet() {
+    let mut rng = thread_rng();
+    let sigma = rng.gen();
+    let vk = ViewingKey::from_sigma(sigma, &*POOL_PARAMS);
+    let other = ViewingKey::from_sigma(rng.gen(), &*POOL_PARAMS);
+    let mut wallet = Wallet::<PoolBN256>::new(vk, 0, &*POOL_PARAMS);
+    let tx_size = (constants::OUT + 1) as u64;
+
+    let account: Account<Fr> = Account::sample(&mut rng, &*POOL_PARAMS);
+    let note_to = |eta, b:u64, rng:&mut _| {
+        let address = Address::generate(eta, rng, &*POOL_PARAMS);
+        Note {d: address.d, p_d: address.p_d, b: BoundedNum::new(Num::from(b)), t: BoundedNum::new(Num::ZERO)}
+    };
+    let notes = [
+        vec![note_to(other.eta(), 10, &mut rng)],
+        vec![note_to(other.eta(), 20, &mut rng), note_to(vk.eta(), 100, &mut rng)],
+        vec![note_to(vk.eta(), 50, &mut rng)],
+    ];
+
+    for (i, n) in notes.iter().enumerate() {
+        let memo = cipher::encrypt(&rng.gen::<[u8; 32]>(), other.eta(), account, n, &*POOL_PARAMS);
+        assert_eq!(wallet.ingest(i as u64 * tx_size, out_commit(&memo), &memo, &*POOL_PARAMS), Ok(i > 0));
+    }
+    assert_eq!(wallet.next_index(), 3 * tx_size);
+    assert_eq!(wallet.unspent_notes(), vec![(tx_size + 2, notes[1][1]), (2 * tx_size + 1, notes[2][0])]);
+    assert_eq!(wallet.balance(), Num::from(150u64));
+    assert_eq!(wallet.energy(), Ok(100 * (2 * tx_size as u128 - 2) + 50 * (tx_size as u128 - 1)));
+    assert!(wallet.account().is_none());
+
+    // note proofs are valid for the tree with foreign transactions
+    let (_, note) = wallet.unspent_notes()[0];
+    assert_eq!(poseidon_merkle_proof_root(note.hash(&*POOL_PARAMS), &wallet.note_proof(tx_size + 2), POOL_PARAMS.compress()), wallet.tree().root());
+
+    // forged memo and wrong index are rejected
+    let forged = cipher::encrypt(&rng.gen::<[u8; 32]>(), other.eta(), account, &[note_to(vk.eta(), 1000, &mut rng)], &*POOL_PARAMS);
+    assert_eq!(wallet.ingest(0, out_commit(&forged), &forged, &*POOL_PARAMS), Err(WalletError::IndexMismatch));
+    assert_eq!(wallet.ingest(3 * tx_size, rng.gen(), &forged, &*POOL_PARAMS), Err(WalletError::CommitmentMismatch));
+    assert_eq!(wallet.next_index(), 4 * tx_size);
+    assert_eq!(wallet.balance(), Num::from(150u64));
+
+    // spend both notes from the initial account
+    let (in_account, in_account_proof) = wallet.input_account(&*POOL_PARAMS);
+    let delta = make_delta::<Fr>(Num::ZERO, Num::ZERO, Num::from(wallet.next_index()), Num::ZERO);
+    let (p, s, memo) = wallet.unspent_notes().into_iter().fold(
+        TxBuilder::new(sigma, in_account, in_account_proof, delta),
+        |b, (i, n)| b.input_note(n, wallet.note_proof(i))
+    )
+        .output(Address::generate(other.eta(), &mut rng, &*POOL_PARAMS), BoundedNum::new(Num::from(30u64)))
+        .root(wallet.tree().root())
+        .build_with_memo(&mut rng, &*POOL_PARAMS).unwrap();
+    assert_eq!(check_transfer(&p, &s, &*POOL_PARAMS), Ok(()));
+
+    let index = wallet.next_index();
+    let energy = wallet.energy().unwrap();
+    assert_eq!(wallet.ingest(index, p.out_commit, &memo, &*POOL_PARAMS), Ok(true));
+    assert_eq!(wallet.account(), Some((index, s.tx.output.0)));
+    assert_eq!(s.tx.output.0.e.to_num(), Num::from(energy));
+    assert!(wallet.unspent_notes().is_empty());
+    assert_eq!(wallet.balance(), Num::from(120u64));
+    assert_eq!(wallet.energy(), Ok(energy + 120 * tx_size as u128));
+
+    // the new account can be spent with the proof from the wallet
+    let (in_account, in_account_proof) = wallet.input_account(&*POOL_PARAMS);
+    let delta = make_delta::<Fr>(Num::ZERO, Num::ZERO, Num::from(wallet.next_index()), Num::ZERO);
+    let (p, s) = TxBuilder::new(sigma, in_account, in_account_proof, delta)
+        .build(&mut rng, &*POOL_PARAMS).unwrap();
+    assert_eq!(p.root, wallet.tree().root());
+    assert_eq!(check_transfer(&p, &s, &*POOL_PARAMS), Ok(()));
+}
+
+#[test]
+fn test_planner() {
+    let mut rng = thread_rng();
+    let mut notes = (0..2 * constants::IN as u64).map(|i| {
+        let mut note: Note<Fr> = Note::sample(&mut rng, &*POOL_PARAMS);
+        note.b = BoundedNum::new(Num::from(10u64));
+        (1000 - i, note)
+    }).collect::<Vec<_>>();
+    notes.reverse();
+    notes.swap(0, 1);
+    let index = |i:usize| 1001 - 2 * constants::IN as u64 + i as u64;
+    let n = constants::IN as u64;
+
+    assert_eq!(plan_inputs(5, &notes, 10 * n + 5), Ok(vec![
+        PlannedTx {notes: (0..constants::IN).map(index).collect(), output_index: Some(index(constants::IN))}
+    ]));
+    assert_eq!(plan_inputs(5, &notes, 10 * n + 6), Ok(vec![
+        PlannedTx {notes: (0..constants::IN).map(index).collect(), output_index: Some(index(constants::IN))},
+        PlannedTx {notes: (constants::IN..2 * constants::IN).map(index).collect(), output_index: None},
+    ]));
+    assert_eq!(plan_inputs(5, &notes, 20 * n + 6), Err(PlanError::InsufficientFunds));
+    assert_eq!(plan_inputs::<Fr>(5, &[], 5), Ok(vec![PlannedTx {notes: vec![], output_index: None}]));
+    assert_eq!(plan_inputs_with_shape::<Fr, 1>(5, &notes, 15), Ok(vec![
+        PlannedTx {notes: vec![index(0)], output_index: Some(index(1))}
+    ]));
+}
+
+#[test]
+fn test_wallet_plan() {
+    let mut rng = thread_rng();
+    let sigma = rng.gen();
+    let vk = ViewingKey::from_sigma(sigma, &*POOL_PARAMS);
+    let other = ViewingKey::from_sigma(rng.gen(), &*POOL_PARAMS);
+    let mut wallet = Wallet::<PoolBN256>::new(vk, 0, &*POOL_PARAMS);
+    let account: Account<Fr> = Account::sample(&mut rng, &*POOL_PARAMS);
+
+    for _ in 0..constants::IN + 1 {
+        let address = Address::generate(vk.eta(), &mut rng, &*POOL_PARAMS);
+        let note = Note {d: address.d, p_d: address.p_d, b: BoundedNum::new(Num::from(10u64)), t: rng.gen()};
+        let memo = cipher::encrypt(&rng.gen::<[u8; 32]>(), other.eta(), account, &[note], &*POOL_PARAMS);
+        assert_eq!(wallet.ingest(wallet.next_index(), out_commit(&memo), &memo, &*POOL_PARAMS), Ok(true));
+    }
+
+    let target = 10 * constants::IN as u64 + 10;
+    let plan = wallet.plan(target).unwrap();
+    assert_eq!(plan.len(), 2);
+    let last_note = wallet.unspent_notes()[constants::IN];
+    assert_eq!(plan[0].output_index, Some(last_note.0));
+
+    // consolidation keeps the last note spendable
+    let delta = make_delta::<Fr>(Num::ZERO, Num::ZERO, Num::from(wallet.next_index()), Num::ZERO);
+    let (p, s, memo) = wallet.tx_builder(sigma, &plan[0], delta, &*POOL_PARAMS).build_with_memo(&mut rng, &*POOL_PARAMS).unwrap();
+    assert_eq!(check_transfer(&p, &s, &*POOL_PARAMS), Ok(()));
+    assert_eq!(wallet.ingest(wallet.next_index(), p.out_commit, &memo, &*POOL_PARAMS), Ok(true));
+    assert_eq!(wallet.unspent_notes(), vec![last_note]);
+    assert_eq!(wallet.balance(), Num::from(target));
+
+    let plan = wallet.plan(target).unwrap();
+    assert_eq!(plan, vec![PlannedTx {notes: vec![last_note.0], output_index: None}]);
+    let delta = make_delta::<Fr>(Num::ZERO, Num::ZERO, Num::from(wallet.next_index()), Num::ZERO);
+    let (p, s) = wallet.tx_builder(sigma, &plan[0], delta, &*POOL_PARAMS)
+        .output(Address::generate(other.eta(), &mut rng, &*POOL_PARAMS), BoundedNum::new(Num::from(target)))
+        .build(&mut rng, &*POOL_PARAMS).unwrap();
+    assert_eq!(check_transfer(&p, &s, &*POOL_PARAMS), Ok(()));
+    assert_eq!(s.tx.output.0.b.to_num(), Num::ZERO);
+
+    // output index can't skip the input account interval
+    let delta = make_delta::<Fr>(Num::ZERO, Num::ZERO, Num::from(wallet.next_index()), Num::ZERO);
+    let builder = wallet.tx_builder(sigma, &PlannedTx {notes: vec![], output_index: Some(0)}, delta, &*POOL_PARAMS);
+    assert_eq!(builder.build(&mut rng, &*POOL_PARAMS).err(), Some(TxBuilderError::InvalidOutputIndex));
+}